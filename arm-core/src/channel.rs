@@ -0,0 +1,114 @@
+//! A lock-free queue from one core to the other. It only needs atomic loads and stores,
+//! which the Cortex-M0+ has.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Holds one less value than `N`, see [`Channel::split`].
+pub struct Channel<T, const N: usize> {
+    buf: UnsafeCell<MaybeUninit<[T; N]>>,
+    /// The index of the next value to receive, only written by the receiver.
+    head: AtomicUsize,
+    /// The index of the next value to send, only written by the sender.
+    tail: AtomicUsize,
+}
+
+// Safety: the sender and the receiver never touch the same slot, see `split`.
+unsafe impl<T: Copy + Send, const N: usize> Sync for Channel<T, N> {}
+
+impl<T: Copy, const N: usize> Channel<T, N> {
+    pub const fn new() -> Self {
+        Channel {
+            buf: UnsafeCell::new(MaybeUninit::uninit()),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the two ends of the channel.
+    ///
+    /// # Safety
+    ///
+    /// May only be called once.
+    pub unsafe fn split(&'static self) -> (Sender<T, N>, Receiver<T, N>) {
+        (Sender(self), Receiver(self))
+    }
+
+    fn slot(&self, index: usize) -> *mut T {
+        // Safety: the index is always less than `N`.
+        unsafe { (self.buf.get() as *mut T).add(index) }
+    }
+
+    /// The number of values that have been sent but not received.
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (tail + N - head) % N
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Copy, const N: usize> Default for Channel<T, N> {
+    fn default() -> Self {
+        Channel::new()
+    }
+}
+
+/// The end of a [`Channel`] that sends.
+pub struct Sender<T: 'static, const N: usize>(&'static Channel<T, N>);
+
+impl<T: Copy, const N: usize> Sender<T, N> {
+    /// Gives the value back if the channel is full.
+    pub fn send(&mut self, value: T) -> Result<(), T> {
+        let tail = self.0.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % N;
+        if next == self.0.head.load(Ordering::Acquire) {
+            return Err(value);
+        }
+        // Safety: the receiver doesn't read the slot until the tail has moved past it.
+        unsafe { self.0.slot(tail).write(value) };
+        self.0.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// The end of a [`Channel`] that receives.
+pub struct Receiver<T: 'static, const N: usize>(&'static Channel<T, N>);
+
+impl<T: Copy, const N: usize> Receiver<T, N> {
+    pub fn peek(&self) -> Option<T> {
+        let head = self.0.head.load(Ordering::Relaxed);
+        if head == self.0.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        // Safety: the sender doesn't write the slot until the head has moved past it.
+        Some(unsafe { self.0.slot(head).read() })
+    }
+
+    pub fn recv(&mut self) -> Option<T> {
+        let value = self.peek()?;
+        let head = self.0.head.load(Ordering::Relaxed);
+        self.0.head.store((head + 1) % N, Ordering::Release);
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
@@ -0,0 +1,74 @@
+//! Compares the arm angles measured by the angle sensors with the angles the steppers
+//! have counted, to notice when a stepper loses steps.
+
+/// The difference between two angles in degrees, in `-180..180`.
+pub fn angle_error(measured: f32, counted: f32) -> f32 {
+    let error = libm::fmodf(measured - counted, 360.0);
+    if error >= 180.0 {
+        error - 360.0
+    } else if error < -180.0 {
+        error + 360.0
+    } else {
+        error
+    }
+}
+
+/// The errors of the bottom and top arms in degrees, the measured angle minus the
+/// counted one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Drift {
+    pub bottom: f32,
+    pub top: f32,
+    /// Set if the counted angles should be corrected by the errors.
+    pub correct: bool,
+}
+
+/// Decides which errors are reported, see [`DriftMonitor::check`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DriftMonitor {
+    /// Set when an error has been reported and not gone away since.
+    reported: bool,
+}
+
+impl DriftMonitor {
+    /// Compares the measured and counted bottom and top arm angles. An error larger than
+    /// `limit` is reported once, until it is back under half of the limit. An error
+    /// larger than `correct` is always reported, to be corrected, unless `correct` is 0.
+    pub fn check(
+        &mut self,
+        (measured_bottom, measured_top): (f32, f32),
+        (counted_bottom, counted_top): (f32, f32),
+        limit: f32,
+        correct: f32,
+    ) -> Option<Drift> {
+        let bottom = angle_error(measured_bottom, counted_bottom);
+        let top = angle_error(measured_top, counted_top);
+        let error = libm::fabsf(bottom).max(libm::fabsf(top));
+        if correct > 0.0 && error > correct {
+            // The error is gone once it has been corrected.
+            self.reported = false;
+            return Some(Drift {
+                bottom,
+                top,
+                correct: true,
+            });
+        }
+        if error > limit && !self.reported {
+            self.reported = true;
+            return Some(Drift {
+                bottom,
+                top,
+                correct: false,
+            });
+        }
+        if error < limit / 2.0 {
+            self.reported = false;
+        }
+        None
+    }
+
+    /// Forgets a reported error, for when the arm has been calibrated.
+    pub fn reset(&mut self) {
+        self.reported = false;
+    }
+}
@@ -0,0 +1,49 @@
+//! Conversions between the arm angles and the stepper angles. The top arm is driven
+//! through the bottom arm, so turning the bottom arm turns the top arm with it, by the
+//! bottom angle divided by the top ratio.
+
+/// The stepper degrees per arm degree of the bottom and top arms, and per meter of the
+/// sideways axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ratios {
+    pub bottom: f32,
+    pub top: f32,
+    pub sideways: f32,
+}
+
+impl Ratios {
+    /// The bottom, top and sideways stepper angles for the arm angles and the sideways
+    /// position.
+    pub fn to_steppers(&self, bottom: f32, top: f32, sideways: f32) -> [f32; 3] {
+        [
+            bottom * self.bottom,
+            (top + bottom / self.top) * self.top,
+            sideways * self.sideways,
+        ]
+    }
+
+    /// The min and max bottom, top and sideways stepper angles for the min and max arm
+    /// angles and sideways positions. The top stepper turns with the bottom arm too, so
+    /// its range is widened by the bottom travel.
+    pub fn travel(
+        &self,
+        bottom: (f32, f32),
+        top: (f32, f32),
+        sideways: (f32, f32),
+    ) -> [(f32, f32); 3] {
+        let min = self.to_steppers(bottom.0, top.0, sideways.0);
+        let max = self.to_steppers(bottom.1, top.1, sideways.1);
+        core::array::from_fn(|i| (min[i], max[i]))
+    }
+
+    /// The arm angles and the sideways position of the stepper angles, the inverse of
+    /// [`Ratios::to_steppers`].
+    pub fn from_steppers(&self, [bottom, top, sideways]: [f32; 3]) -> (f32, f32, f32) {
+        let bottom = bottom / self.bottom;
+        (
+            bottom,
+            top / self.top - bottom / self.top,
+            sideways / self.sideways,
+        )
+    }
+}
@@ -0,0 +1,24 @@
+//! The motion logic of the arm firmware. It only knows the hardware through the
+//! embedded-hal pin and I2C traits and [`Clock`], so that it can be tested on the host
+//! with mock pins and a simulated clock. The firmware in `arm` provides the real ones.
+#![no_std]
+
+pub mod channel;
+pub mod drift;
+pub mod joints;
+pub mod motion;
+pub mod sensor;
+pub mod stepper;
+
+/// A microsecond counter that wraps around.
+pub trait Clock {
+    fn now_us(&self) -> u32;
+
+    /// Waits `us` microseconds, for when nothing else has to run meanwhile.
+    fn wait_us(&self, us: u32) {
+        let start = self.now_us();
+        while self.now_us().wrapping_sub(start) < us {
+            core::hint::spin_loop();
+        }
+    }
+}
@@ -0,0 +1,462 @@
+//! The queued movements. The movements are started one after the other, blended
+//! together unless the path turns sharply, with all steppers ramped together so that
+//! they start and stop at the same time.
+
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+pub use robby_fischer::Axis;
+use robby_fischer::{Event, MotionState};
+
+use crate::{
+    channel::{Receiver, Sender},
+    stepper::{convert_position, HomingError, Profile, StepSize, Stepper},
+    Clock,
+};
+
+/// One more than the max number of movements that fit in the queue.
+pub const QUEUE_CAPACITY: usize = 512;
+/// One more than the max number of events waiting to be sent.
+pub const EVENT_CAPACITY: usize = 32;
+
+/// A queued movement starts this many seconds before the one before it ends, so that
+/// the steppers don't stop in between.
+const BLEND_TIME: f32 = 0.002;
+/// The steppers stop between two queued movements if the cosine of the angle between
+/// their directions is less than this, about 25 degrees.
+const SHARP_TURN_COS: f32 = 0.9;
+/// How far in degrees the steppers may be from their targets and still count as there,
+/// 3 microsteps at 1/16 step size.
+const POSITION_MARGIN: f32 = 0.34;
+
+/// A queued movement, the bottom, top and sideways stepper angles and the speed factor.
+pub type Movement = (f32, f32, f32, f32);
+
+/// The max speeds and accelerations of the bottom, top and sideways steppers.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub max_speeds: [f32; 3],
+    pub max_accels: [f32; 3],
+    pub profile: Profile,
+    /// The min and max stepper angles, see [`Stepper::set_travel_limits`].
+    pub travel: [(f32, f32); 3],
+}
+
+/// Changes the motion from outside of the queue, see [`Motion::handle`].
+#[derive(Clone, Copy, Debug)]
+pub enum Request {
+    /// Goes to the stepper angle at a fixed velocity, without a ramp and regardless of
+    /// the queue.
+    Move {
+        axis: Axis,
+        angle: f32,
+        velocity: f32,
+    },
+    /// Sets the bottom and top stepper angles, read from the angle sensors.
+    CalibrateArm {
+        bottom: f32,
+        top: f32,
+    },
+    /// Adds the errors in degrees to the counted bottom and top stepper angles, see
+    /// [`Stepper::correct_angle`].
+    CorrectArm {
+        bottom: f32,
+        top: f32,
+    },
+    /// Sets the bottom, top and sideways stepper angles, saved before a power cycle.
+    Restore([f32; 3]),
+    /// Moves the sideways stepper to the limit switch, giving up after `max_travel`
+    /// stepper degrees or `timeout_us`.
+    CalibrateSideways {
+        max_travel: f32,
+        timeout_us: u32,
+    },
+    Stop,
+    Pause,
+    Resume,
+    ClearQueue,
+    SetLimits(Limits),
+    /// See [`Stepper::set_step_size`].
+    SetStepSize(Axis, StepSize),
+    /// See [`Stepper::set_enabled`].
+    Enable(Axis, bool),
+}
+
+/// The steppers and the state of the queued movements.
+pub struct Motion<P, B, C> {
+    pub bottom_arm_stepper: Stepper<P>,
+    pub top_arm_stepper: Stepper<P>,
+    pub sideways_stepper: Stepper<P>,
+    sideways_button: B,
+    sideways_button_last_state: bool,
+    clock: C,
+    limits: Limits,
+
+    movements: Receiver<Movement, QUEUE_CAPACITY>,
+    events: Sender<Event, EVENT_CAPACITY>,
+
+    /// Set when a queued movement starts, cleared when the queue is drained.
+    draining: bool,
+    /// The bottom, top and sideways targets to go back to when resuming, set while paused.
+    paused_targets: Option<[i64; 3]>,
+    /// Set when the steppers have started to ramp down at the end of the queue.
+    stopping: bool,
+    /// The direction of the current queued movement, see [`Motion::is_sharp_turn`].
+    segment_direction: [f32; 3],
+    /// See [`Motion::dequeued`].
+    dequeued: u32,
+}
+
+impl<P: OutputPin, B: InputPin, C: Clock> Motion<P, B, C> {
+    /// Takes the movements to run from `movements` and sends events like
+    /// [`Event::SegmentStarted`] to `events`.
+    pub fn new(
+        [bottom_arm_stepper, top_arm_stepper, sideways_stepper]: [Stepper<P>; 3],
+        sideways_button: B,
+        clock: C,
+        limits: Limits,
+        movements: Receiver<Movement, QUEUE_CAPACITY>,
+        events: Sender<Event, EVENT_CAPACITY>,
+    ) -> Self {
+        let mut motion = Motion {
+            bottom_arm_stepper,
+            top_arm_stepper,
+            sideways_stepper,
+            sideways_button,
+            sideways_button_last_state: false,
+            clock,
+            limits,
+            movements,
+            events,
+            draining: false,
+            paused_targets: None,
+            stopping: false,
+            segment_direction: [0.0; 3],
+            dequeued: 0,
+        };
+        motion.set_limits(limits);
+        motion
+    }
+
+    /// Starts the next queued movement if it is time and steps the steppers that are due.
+    /// Has to be called much more often than the steppers step.
+    pub fn run(&mut self) {
+        let limit = self.sideways_button.is_low().unwrap_or(false);
+        if !self.sideways_button_last_state && limit {
+            self.push_event(Event::LimitSwitch);
+        }
+        self.sideways_button_last_state = limit;
+
+        self.check_queue();
+        self.sideways_stepper.run(&self.clock);
+        self.bottom_arm_stepper.run(&self.clock);
+        self.top_arm_stepper.run(&self.clock);
+    }
+
+    /// The bottom, top and sideways stepper angles in degrees.
+    pub fn angles(&self) -> [f32; 3] {
+        [
+            self.bottom_arm_stepper.get_angle(),
+            self.top_arm_stepper.get_angle(),
+            self.sideways_stepper.get_angle(),
+        ]
+    }
+
+    /// The number of movements taken from the queue, started or cleared, wrapping. The
+    /// queue has room for as many more.
+    pub fn dequeued(&self) -> u32 {
+        self.dequeued
+    }
+
+    pub fn state(&self) -> MotionState {
+        if self.paused_targets.is_some() {
+            MotionState::Paused
+        } else if self.draining || !self.is_in_position_margin(POSITION_MARGIN) {
+            MotionState::Moving
+        } else {
+            MotionState::Idle
+        }
+    }
+
+    /// Events are dropped if the receiver doesn't keep up.
+    fn push_event(&mut self, event: Event) {
+        let _ = self.events.send(event);
+    }
+
+    /// Only [`Request::CalibrateSideways`] can fail.
+    pub fn handle(&mut self, request: Request) -> Result<(), HomingError> {
+        match request {
+            Request::Move {
+                axis,
+                angle,
+                velocity,
+            } => {
+                let stepper = self.stepper(axis);
+                stepper.set_velocity(velocity);
+                stepper.goto_angle(angle);
+            }
+            Request::CalibrateArm { bottom, top } => {
+                self.bottom_arm_stepper.calib_real_angle(bottom);
+                self.top_arm_stepper.calib_real_angle(top);
+            }
+            Request::Restore([bottom, top, sideways]) => {
+                self.bottom_arm_stepper.calib_real_angle(bottom);
+                self.top_arm_stepper.calib_real_angle(top);
+                self.sideways_stepper.calib_real_angle(sideways);
+            }
+            Request::CorrectArm { bottom, top } => {
+                self.bottom_arm_stepper.correct_angle(bottom);
+                self.top_arm_stepper.correct_angle(top);
+            }
+            Request::CalibrateSideways {
+                max_travel,
+                timeout_us,
+            } => {
+                let result = self.sideways_stepper.calibrate(
+                    &mut self.sideways_button,
+                    20.0,
+                    500.,
+                    max_travel,
+                    timeout_us,
+                    &self.clock,
+                );
+                // The limit switch was hit on purpose.
+                self.sideways_button_last_state = self.sideways_button.is_low().unwrap_or(false);
+                return result;
+            }
+            Request::Stop => {
+                self.halt();
+                self.clear_queue();
+                self.paused_targets = None;
+                self.draining = false;
+                self.push_event(Event::Stopped);
+            }
+            Request::Pause => {
+                if self.paused_targets.is_none() {
+                    self.paused_targets = Some([
+                        self.bottom_arm_stepper.target_pos,
+                        self.top_arm_stepper.target_pos,
+                        self.sideways_stepper.target_pos,
+                    ]);
+                    self.halt();
+                    self.push_event(Event::Paused);
+                }
+            }
+            Request::Resume => {
+                if let Some([bottom, top, sideways]) = self.paused_targets.take() {
+                    self.bottom_arm_stepper.target_pos = bottom;
+                    self.top_arm_stepper.target_pos = top;
+                    self.sideways_stepper.target_pos = sideways;
+                    self.ramp_all([
+                        self.bottom_arm_stepper.cruise_velocity(),
+                        self.top_arm_stepper.cruise_velocity(),
+                        self.sideways_stepper.cruise_velocity(),
+                    ]);
+                    self.stopping = false;
+                    self.push_event(Event::Resumed);
+                }
+            }
+            Request::ClearQueue => {
+                self.clear_queue();
+            }
+            Request::SetLimits(limits) => {
+                self.set_limits(limits);
+            }
+            Request::SetStepSize(axis, step_size) => {
+                let from = self.stepper(axis).step_size();
+                self.stepper(axis).set_step_size(step_size);
+                // Converted like the positions of the stepper.
+                if let Some(targets) = &mut self.paused_targets {
+                    let target = &mut targets[axis as usize];
+                    *target = convert_position(*target, from, step_size);
+                }
+            }
+            Request::Enable(axis, enabled) => {
+                self.stepper(axis).set_enabled(enabled);
+            }
+        }
+        Ok(())
+    }
+
+    fn stepper(&mut self, axis: Axis) -> &mut Stepper<P> {
+        match axis {
+            Axis::Bottom => &mut self.bottom_arm_stepper,
+            Axis::Top => &mut self.top_arm_stepper,
+            Axis::Sideways => &mut self.sideways_stepper,
+        }
+    }
+
+    fn set_limits(&mut self, limits: Limits) {
+        let [bottom, top, sideways] = limits.travel;
+        self.bottom_arm_stepper
+            .set_travel_limits(bottom.0, bottom.1);
+        self.top_arm_stepper.set_travel_limits(top.0, top.1);
+        self.sideways_stepper
+            .set_travel_limits(sideways.0, sideways.1);
+        self.limits = limits;
+    }
+
+    fn clear_queue(&mut self) {
+        while self.movements.recv().is_some() {
+            self.dequeued = self.dequeued.wrapping_add(1);
+        }
+    }
+
+    /// Stops all steppers where they are.
+    fn halt(&mut self) {
+        self.bottom_arm_stepper.halt();
+        self.top_arm_stepper.halt();
+        self.sideways_stepper.halt();
+    }
+
+    /// True if all steppers are within `margin` degrees of their targets.
+    pub fn is_in_position_margin(&self, margin: f32) -> bool {
+        self.top_arm_stepper.is_at_target_within(margin)
+            && self.bottom_arm_stepper.is_at_target_within(margin)
+            && self.sideways_stepper.is_at_target_within(margin)
+    }
+
+    fn check_queue(&mut self) {
+        if self.paused_targets.is_some() {
+            return;
+        }
+        let Some(next) = self.movements.peek() else {
+            if self.draining {
+                if self.is_in_position_margin(POSITION_MARGIN) {
+                    self.draining = false;
+                    self.push_event(Event::QueueDrained);
+                } else if !self.stopping {
+                    self.ramp_down_near_end();
+                }
+            }
+            return;
+        };
+        if !self.draining {
+            if self.is_in_position_margin(POSITION_MARGIN) {
+                self.start_segment();
+            }
+            return;
+        }
+        // The next movement is started just before the current one ends, unless the
+        // path turns so much that the steppers have to slow down first.
+        if self.is_sharp_turn(next) {
+            if self.is_in_position_margin(POSITION_MARGIN) {
+                self.start_segment();
+            } else if !self.stopping {
+                self.ramp_down_near_end();
+            }
+        } else if self.time_left() <= BLEND_TIME {
+            self.start_segment();
+        }
+    }
+
+    /// Takes the next queued movement and ramps to its velocities.
+    fn start_segment(&mut self) {
+        let (a1, a2, sd, speed_scale_factor) = self.movements.recv().unwrap();
+        self.dequeued = self.dequeued.wrapping_add(1);
+        let speed_scale_factor = (1.0_f32).min(speed_scale_factor);
+        let [bot_speed, top_speed, sideways_speed] = self.limits.max_speeds;
+        let max_time = ((libm::fabsf(self.bottom_arm_stepper.get_angle() - a1) / bot_speed)
+            .max(libm::fabsf(self.top_arm_stepper.get_angle() - a2) / top_speed)
+            .max(libm::fabsf(self.sideways_stepper.get_angle() - sd) / sideways_speed)
+            + 0.0001)
+            / speed_scale_factor;
+
+        let velocities = [
+            (self.bottom_arm_stepper.get_angle() - a1) / max_time,
+            (self.top_arm_stepper.get_angle() - a2) / max_time,
+            (self.sideways_stepper.get_angle() - sd) / max_time,
+        ];
+
+        self.segment_direction = self.direction_to((a1, a2, sd));
+        self.bottom_arm_stepper.goto_angle(a1);
+        self.top_arm_stepper.goto_angle(a2);
+        self.sideways_stepper.goto_angle(sd);
+        self.ramp_all(velocities);
+        self.stopping = false;
+
+        self.draining = true;
+        self.push_event(Event::SegmentStarted(self.movements.len() as u32));
+    }
+
+    /// The time it takes the slowest stepper to reach its target.
+    fn time_left(&self) -> f32 {
+        self.bottom_arm_stepper
+            .time_left()
+            .max(self.top_arm_stepper.time_left())
+            .max(self.sideways_stepper.time_left())
+    }
+
+    /// The direction from the current targets to `(a1, a2, sd)`, with the axes scaled by
+    /// their max speeds so that the directions tell how the velocities have to change.
+    fn direction_to(&self, (a1, a2, sd): (f32, f32, f32)) -> [f32; 3] {
+        let [bot_speed, top_speed, sideways_speed] = self.limits.max_speeds;
+        [
+            (a1 - self.bottom_arm_stepper.target_angle()) / bot_speed,
+            (a2 - self.top_arm_stepper.target_angle()) / top_speed,
+            (sd - self.sideways_stepper.target_angle()) / sideways_speed,
+        ]
+    }
+
+    /// True if the direction of the queued movement `next` differs from the current one
+    /// by more than [`SHARP_TURN_COS`] allows.
+    fn is_sharp_turn(&self, (a1, a2, sd, _): Movement) -> bool {
+        let next = self.direction_to((a1, a2, sd));
+        let current = self.segment_direction;
+        let dot: f32 = (0..3).map(|i| current[i] * next[i]).sum();
+        let current_len: f32 = current.iter().map(|v| v * v).sum();
+        let next_len: f32 = next.iter().map(|v| v * v).sum();
+        let len = libm::sqrtf(current_len * next_len);
+        len > 0.0 && dot / len < SHARP_TURN_COS
+    }
+
+    /// Changes the bottom, top and sideways velocities together, in the time it takes the
+    /// axis that is slowest to accelerate. The axes start and stop together, which keeps
+    /// the claw on the line between the queued positions.
+    fn ramp_all(&mut self, velocities: [f32; 3]) {
+        let profile = self.limits.profile;
+        let duration = self.ramp_duration(velocities);
+        self.bottom_arm_stepper
+            .ramp_to(velocities[0], duration, profile);
+        self.top_arm_stepper
+            .ramp_to(velocities[1], duration, profile);
+        self.sideways_stepper
+            .ramp_to(velocities[2], duration, profile);
+    }
+
+    fn ramp_duration(&self, velocities: [f32; 3]) -> f32 {
+        let steppers = [
+            &self.bottom_arm_stepper,
+            &self.top_arm_stepper,
+            &self.sideways_stepper,
+        ];
+        let mut duration = 0.0_f32;
+        for ((stepper, accel), velocity) in steppers
+            .into_iter()
+            .zip(self.limits.max_accels)
+            .zip(velocities)
+        {
+            let change = libm::fabsf(libm::fabsf(velocity) - stepper.velocity_towards_target());
+            duration = duration.max(change / accel);
+        }
+        duration * self.limits.profile.peak_factor()
+    }
+
+    /// Starts to ramp down when the last queued movement is about to end, so that the
+    /// steppers stop at their targets.
+    fn ramp_down_near_end(&mut self) {
+        let duration = self.ramp_duration([0.0; 3]);
+        let near_end = [
+            &self.bottom_arm_stepper,
+            &self.top_arm_stepper,
+            &self.sideways_stepper,
+        ]
+        .iter()
+        // A stepper that is already at its target isn't moving, and says nothing about
+        // when the others have to slow down.
+        .filter(|stepper| stepper.remaining_steps() > 0)
+        .any(|stepper| stepper.remaining_steps() as f32 <= stepper.stopping_distance(duration));
+        if near_end {
+            self.ramp_all([0.0; 3]);
+            self.stopping = true;
+        }
+    }
+}
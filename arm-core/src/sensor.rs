@@ -0,0 +1,282 @@
+//! The MLX90393 magnetometers that measure the arm angles.
+
+use core::f32::consts::PI;
+
+use embedded_hal::blocking::{delay::DelayMs, i2c};
+use mlx90393::{DigitalFilter, I2CInterface, Magnetometer, OverSamplingRatio};
+
+use crate::drift::angle_error;
+
+/// The number of entries in [`Calibration::table`], one every 15 degrees.
+pub const TABLE_LEN: usize = 24;
+/// The number of measurements [`AngleSensor::get_angle`] averages.
+const SAMPLES: usize = 5;
+/// Measurements further than this many degrees from the median are left out of the
+/// average.
+const OUTLIER_LIMIT: f32 = 2.0;
+
+/// Corrects the measured field for the magnet not being centered over the sensor and the
+/// sensor not being level, which make the field an offset ellipse instead of a circle
+/// around zero. See [`Calibration::fit`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    /// The hard-iron offset of the field.
+    pub center: (f32, f32),
+    /// Turns the soft-iron ellipse around the center into a unit circle.
+    pub inverse: [[f32; 2]; 2],
+    /// Degrees added to the angle of the circle, every `360 / TABLE_LEN` degrees from -180
+    /// and interpolated in between. What is left of the error after the ellipse.
+    pub table: [f32; TABLE_LEN],
+}
+
+impl Calibration {
+    /// Leaves the raw angle of the field as it is.
+    pub const NONE: Calibration = Calibration {
+        center: (0.0, 0.0),
+        inverse: [[1.0, 0.0], [0.0, 1.0]],
+        table: [0.0; TABLE_LEN],
+    };
+
+    /// The corrected angle in degrees of the field `(x, y)`.
+    pub fn angle(&self, x: f32, y: f32) -> f32 {
+        let angle = self.ellipse_angle(x, y);
+        angle + self.table_correction(angle)
+    }
+
+    fn ellipse_angle(&self, x: f32, y: f32) -> f32 {
+        let (x, y) = (x - self.center.0, y - self.center.1);
+        let [[a, b], [c, d]] = self.inverse;
+        libm::atan2f(c * x + d * y, a * x + b * y) * 180.0 / PI
+    }
+
+    fn table_correction(&self, angle: f32) -> f32 {
+        let position = (angle + 180.0) / (360.0 / TABLE_LEN as f32);
+        let floor = libm::floorf(position);
+        let i = floor as usize % TABLE_LEN;
+        let t = position - floor;
+        self.table[i] * (1.0 - t) + self.table[(i + 1) % TABLE_LEN] * t
+    }
+
+    /// Fits a calibration to measured fields `(angle, x, y)`, where the angle in degrees
+    /// is known from elsewhere, like the steppers. The field is modelled as
+    /// `x = cx + m00 cos(angle) + m01 sin(angle)` and the same for `y`, which is a least
+    /// squares fit for each. The table gets the average error left at each entry, it
+    /// stays zero outside of the angles that were measured.
+    ///
+    /// Returns `None` if the angles don't spread out enough to tell the ellipse.
+    pub fn fit(samples: &[(f32, f32, f32)]) -> Option<Calibration> {
+        let mut ata = [[0.0; 3]; 3];
+        let mut atx = [0.0; 3];
+        let mut aty = [0.0; 3];
+        for &(angle, x, y) in samples {
+            let radians = angle * PI / 180.0;
+            let row = [1.0, libm::cosf(radians), libm::sinf(radians)];
+            for i in 0..3 {
+                for j in 0..3 {
+                    ata[i][j] += row[i] * row[j];
+                }
+                atx[i] += row[i] * x;
+                aty[i] += row[i] * y;
+            }
+        }
+        let [cx, m00, m01] = solve(ata, atx)?;
+        let [cy, m10, m11] = solve(ata, aty)?;
+        let det = m00 * m11 - m01 * m10;
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+
+        let mut calibration = Calibration {
+            center: (cx, cy),
+            inverse: [[m11 / det, -m01 / det], [-m10 / det, m00 / det]],
+            table: [0.0; TABLE_LEN],
+        };
+        let step = 360.0 / TABLE_LEN as f32;
+        let mut sums = [0.0; TABLE_LEN];
+        let mut counts = [0_u32; TABLE_LEN];
+        for &(angle, x, y) in samples {
+            let measured = calibration.ellipse_angle(x, y);
+            let i = libm::roundf((measured + 180.0) / step) as usize % TABLE_LEN;
+            sums[i] += angle_error(angle, measured);
+            counts[i] += 1;
+        }
+        for ((entry, sum), count) in calibration.table.iter_mut().zip(sums).zip(counts) {
+            if count > 0 {
+                *entry = sum / count as f32;
+            }
+        }
+        Some(calibration)
+    }
+
+    /// The root mean square error in degrees of the corrected angles of `samples`, see
+    /// [`Calibration::fit`].
+    pub fn rms_error(&self, samples: &[(f32, f32, f32)]) -> f32 {
+        let sum: f32 = samples
+            .iter()
+            .map(|&(angle, x, y)| angle_error(angle, self.angle(x, y)))
+            .map(|error| error * error)
+            .sum();
+        libm::sqrtf(sum / samples.len().max(1) as f32)
+    }
+}
+
+/// Solves `a * x = b` with Cramer's rule, `None` if `a` is singular.
+fn solve(a: [[f32; 3]; 3], b: [f32; 3]) -> Option<[f32; 3]> {
+    fn det(m: [[f32; 3]; 3]) -> f32 {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+    let d = det(a);
+    // The determinant grows with the cube of the number of samples.
+    let scale = a[0][0] * a[0][0] * a[0][0];
+    if d.is_nan() || libm::fabsf(d) <= scale * 1e-6 {
+        return None;
+    }
+    let mut x = [0.0; 3];
+    for (i, x) in x.iter_mut().enumerate() {
+        let mut m = a;
+        for row in 0..3 {
+            m[row][i] = b[row];
+        }
+        *x = det(m) / d;
+    }
+    Some(x)
+}
+
+/// An angle averaged from several measurements, see [`filter`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    /// In degrees.
+    pub angle: f32,
+    /// The standard error of the average in degrees.
+    pub uncertainty: f32,
+    /// The number of measurements that were not left out.
+    pub samples: usize,
+}
+
+/// Averages angles in degrees, leaving out the ones further than [`OUTLIER_LIMIT`] from
+/// the median. Panics if `angles` is empty.
+pub fn filter(angles: &mut [f32]) -> Reading {
+    // Unwrapped around the first angle, so that angles on both sides of 180 average
+    // to 180 and not to 0.
+    let reference = angles[0];
+    for angle in angles.iter_mut() {
+        *angle = reference + angle_error(*angle, reference);
+    }
+    angles.sort_unstable_by(f32::total_cmp);
+    let median = angles[angles.len() / 2];
+    let kept = || {
+        angles
+            .iter()
+            .filter(move |&&angle| libm::fabsf(angle - median) <= OUTLIER_LIMIT)
+    };
+    let samples = kept().count();
+    let mean = kept().sum::<f32>() / samples as f32;
+    let uncertainty = if samples > 1 {
+        let variance = kept()
+            .map(|angle| (angle - mean) * (angle - mean))
+            .sum::<f32>()
+            / (samples - 1) as f32;
+        libm::sqrtf(variance / samples as f32)
+    } else {
+        OUTLIER_LIMIT
+    };
+    Reading {
+        angle: mean,
+        uncertainty,
+        samples,
+    }
+}
+
+pub struct AngleSensor {
+    pub mlx: Magnetometer,
+    pub address: u8,
+    pub calibration: Calibration,
+}
+
+impl AngleSensor {
+    pub fn new<WR, E>(i2c: &mut WR, address: u8) -> Result<Self, mlx90393::Error<E>>
+    where
+        WR: i2c::WriteRead<Error = E>,
+    {
+        let mut protocol = I2CInterface { i2c, address };
+        let mut mlx = Magnetometer::default_settings(&mut protocol)?;
+        mlx.set_filter(&mut protocol, DigitalFilter::DF4)?;
+        mlx.set_oversampling_ratio(&mut protocol, OverSamplingRatio::OSR4)?;
+
+        // mlx.
+        Ok(AngleSensor {
+            mlx,
+            address,
+            calibration: Calibration::NONE,
+        })
+    }
+
+    /// The corrected angle in degrees, averaged from [`SAMPLES`] measurements.
+    pub fn get_angle<WR, E>(
+        &mut self,
+        i2c: &mut WR,
+        delay: &mut impl DelayMs<u32>,
+    ) -> Result<Reading, mlx90393::Error<E>>
+    where
+        WR: i2c::WriteRead<Error = E>,
+    {
+        let mut angles = [0.0; SAMPLES];
+        for angle in &mut angles {
+            let (x, y) = self.get_field(i2c, delay)?;
+            *angle = self.calibration.angle(x, y);
+        }
+        Ok(filter(&mut angles))
+    }
+
+    /// The raw x and y field of one measurement.
+    pub fn get_field<WR, E>(
+        &mut self,
+        i2c: &mut WR,
+        delay: &mut impl DelayMs<u32>,
+    ) -> Result<(f32, f32), mlx90393::Error<E>>
+    where
+        WR: i2c::WriteRead<Error = E>,
+    {
+        let mut protocol = I2CInterface {
+            i2c,
+            address: self.address,
+        };
+        let (_t, x, y, _z) = self.mlx.do_measurement(&mut protocol, delay)?;
+        // println!("{} {}", x, y);
+        Ok((x as f32, y as f32))
+    }
+}
+
+/// The bottom arm angle in `0..360` from the angle of its sensor.
+pub fn bottom_arm_angle(sensor_angle: f32, offset: f32) -> f32 {
+    let angle = sensor_angle + offset;
+    if angle < 0.0 {
+        angle + 360.0
+    } else {
+        angle
+    }
+}
+
+/// The top arm angle in `0..360` from the angle of its sensor, which is mounted the
+/// other way around and a quarter turn off.
+pub fn top_arm_angle(sensor_angle: f32, offset: f32) -> f32 {
+    let angle = -(sensor_angle + offset) - 90.0;
+    if angle < 0.0 {
+        angle + 360.0
+    } else {
+        angle
+    }
+}
+
+/// The angle of the bottom sensor at a bottom arm angle, the inverse of
+/// [`bottom_arm_angle`].
+pub fn bottom_sensor_angle(arm_angle: f32, offset: f32) -> f32 {
+    angle_error(arm_angle - offset, 0.0)
+}
+
+/// The angle of the top sensor at a top arm angle, the inverse of [`top_arm_angle`].
+pub fn top_sensor_angle(arm_angle: f32, offset: f32) -> f32 {
+    angle_error(-arm_angle - 90.0 - offset, 0.0)
+}
@@ -0,0 +1,478 @@
+//! A stepper driver with a step, a direction, three microstep and an enable pin, which
+//! all have the same pin type.
+
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::Clock;
+
+const STEPS_PER_REVOLUTION: u32 = 200;
+const MAX_REVOLUTIONS_PER_SECOND: f32 = 6.25;
+const MAX_VELOCITY: f32 = MAX_REVOLUTIONS_PER_SECOND * STEPS_PER_REVOLUTION as f32;
+/// The slowest a ramp makes a stepper go, so that it still reaches its target after
+/// ramping down to zero.
+const MIN_RAMP_VELOCITY: f32 = 10.0;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum StepSize {
+    DIV1 = 1,
+    DIV2 = 2,
+    DIV4 = 4,
+    DIV8 = 8,
+    DIV16 = 16,
+}
+
+/// Converts a position in steps of one size to another, rounded to the nearest step and
+/// away from zero at halves. The positions that stand for no limit are kept.
+pub(crate) fn convert_position(pos: i64, from: StepSize, to: StepSize) -> i64 {
+    let (from, to) = (from as i64, to as i64);
+    if pos == i64::MIN || pos == i64::MAX {
+        pos
+    } else {
+        (2 * pos * to + pos.signum() * from) / (2 * from)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// How the velocity changes during a ramp, see [`Stepper::ramp_to`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    /// Constant acceleration, the velocity is a trapezoid over a whole movement.
+    Trapezoidal,
+    /// The acceleration rises and falls smoothly, which makes the velocity an S-curve.
+    SCurve,
+}
+
+impl Profile {
+    /// How far the velocity has changed at `t` of the ramp, both from 0 to 1.
+    fn shape(self, t: f32) -> f32 {
+        match self {
+            Profile::Trapezoidal => t,
+            Profile::SCurve => t * t * (3.0 - 2.0 * t),
+        }
+    }
+
+    /// The peak acceleration of a ramp divided by its average acceleration.
+    pub fn peak_factor(self) -> f32 {
+        match self {
+            Profile::Trapezoidal => 1.0,
+            Profile::SCurve => 1.5,
+        }
+    }
+}
+
+/// Why [`Stepper::calibrate`] gave up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HomingError {
+    /// The button did not change within the timeout.
+    Timeout = 1,
+    /// The stepper went further than the max travel, like when the button is unplugged.
+    MaxTravel = 2,
+}
+
+struct Ramp {
+    from: f32,
+    to: f32,
+    /// Set by the first [`Stepper::run`] of the ramp.
+    start_us: Option<u32>,
+    duration_us: u32,
+    profile: Profile,
+}
+
+pub struct Stepper<P> {
+    step_size: StepSize,
+    step_pin: P,
+    step_is_high: bool,
+    dir_pin: P,
+
+    pub cur_pos: i64, // In number of sixtenth steps
+    pub target_pos: i64,
+    /// The targets are kept between these, see [`Stepper::set_travel_limits`].
+    min_pos: i64,
+    max_pos: i64,
+
+    time_us_last_step: u32,
+    step_time_us: u32,
+    /// In degrees per second, changed by ramps.
+    velocity: f32,
+    /// The velocity of the last ramp or [`Stepper::set_velocity`].
+    cruise_velocity: f32,
+    ramp: Option<Ramp>,
+    pub positive_direction: Direction,
+    pub cur_direction: Direction,
+    mode_pins: Option<(P, P, P)>,
+    /// Low while the driver is on.
+    enable_pin: Option<P>,
+    enabled: bool,
+}
+
+impl<P: OutputPin> Stepper<P> {
+    /// Changes the microstepping. The positions are converted to the new step size, so
+    /// the angles stay the same, rounded to whole steps when the steps get larger.
+    pub fn set_step_size(&mut self, step_size: StepSize) {
+        let (from, to) = (self.step_size, step_size);
+        self.cur_pos = convert_position(self.cur_pos, from, to);
+        self.target_pos = convert_position(self.target_pos, from, to);
+        self.min_pos = convert_position(self.min_pos, from, to);
+        self.max_pos = convert_position(self.max_pos, from, to);
+        self.step_time_us = (self.step_time_us as i64 * from as i64 / to as i64) as u32;
+        self.step_size = step_size;
+        self.write_mode_pins();
+    }
+
+    pub fn step_size(&self) -> StepSize {
+        self.step_size
+    }
+
+    fn write_mode_pins(&mut self) {
+        if let Some((ms1, ms2, ms3)) = &mut self.mode_pins {
+            match self.step_size {
+                StepSize::DIV1 => {
+                    let _ = ms1.set_low();
+                    let _ = ms2.set_low();
+                    let _ = ms3.set_low();
+                }
+                StepSize::DIV2 => {
+                    let _ = ms1.set_high();
+                    let _ = ms2.set_low();
+                    let _ = ms3.set_low();
+                }
+                StepSize::DIV4 => {
+                    let _ = ms1.set_low();
+                    let _ = ms2.set_high();
+                    let _ = ms3.set_low();
+                }
+                StepSize::DIV8 => {
+                    let _ = ms1.set_high();
+                    let _ = ms2.set_high();
+                    let _ = ms3.set_low();
+                }
+                StepSize::DIV16 => {
+                    let _ = ms1.set_high();
+                    let _ = ms2.set_high();
+                    let _ = ms3.set_high();
+                }
+            }
+        }
+    }
+
+    pub fn new(
+        step_pin: P,
+        dir_pin: P,
+        step_size: StepSize,
+        positive_direction: Direction,
+        mode_pins: Option<(P, P, P)>,
+        enable_pin: Option<P>,
+    ) -> Self {
+        let mut stepper = Stepper {
+            step_size,
+            step_pin,
+            dir_pin,
+            mode_pins,
+            enable_pin,
+            enabled: false,
+            cur_pos: 0,
+            target_pos: 0,
+            min_pos: i64::MIN,
+            max_pos: i64::MAX,
+            step_is_high: false,
+            time_us_last_step: 0,
+            step_time_us: 4000,
+            velocity: 0.0,
+            cruise_velocity: 0.0,
+            ramp: None,
+            cur_direction: positive_direction,
+            positive_direction,
+        };
+
+        // Write to the directions pin.
+        stepper.set_direction(stepper.cur_direction);
+
+        stepper.write_mode_pins();
+        stepper.set_enabled(true);
+        stepper
+    }
+
+    /// Turns the driver on or off. The motor turns freely while it is off, so the stepper
+    /// stops and doesn't know where it is anymore.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.halt();
+        }
+        self.enabled = enabled;
+        if let Some(pin) = &mut self.enable_pin {
+            let _ = if enabled {
+                pin.set_low()
+            } else {
+                pin.set_high()
+            };
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Moves backwards until the button is pressed, then forwards until it is released,
+    /// which becomes position zero. Gives up after `max_travel` degrees or `timeout_us`
+    /// in total, and then stays where it is.
+    pub fn calibrate<B: InputPin>(
+        &mut self,
+        button_pin: &mut B,
+        slow_velocity: f32,
+        fast_velocity: f32,
+        max_travel: f32,
+        timeout_us: u32,
+        clock: &impl Clock,
+    ) -> Result<(), HomingError> {
+        let old_step_time = self.step_time_us;
+        let start_us = clock.now_us();
+        // A step takes two calls to `step`.
+        let max_calls = (2.0 * max_travel * self.steps_per_degree()) as u32;
+        let mut calls = 0;
+        let mut result = Ok(());
+        'homing: for (velocity, direction, pressed) in [
+            (fast_velocity, !self.positive_direction, false),
+            (slow_velocity, self.positive_direction, true),
+        ] {
+            self.set_velocity(velocity);
+            self.set_direction(direction);
+            while button_pin.is_low().ok() == Some(pressed) {
+                if calls >= max_calls {
+                    result = Err(HomingError::MaxTravel);
+                    break 'homing;
+                }
+                if clock.now_us().wrapping_sub(start_us) >= timeout_us {
+                    result = Err(HomingError::Timeout);
+                    break 'homing;
+                }
+                self.step();
+                calls += 1;
+                clock.wait_us(self.step_time_us);
+            }
+        }
+        self.step_time_us = old_step_time;
+        if result.is_ok() {
+            self.cur_pos = 0;
+        }
+        self.halt();
+        result
+    }
+
+    /// Gets angle in degrees from start.
+    pub fn get_angle(&self) -> f32 {
+        self.cur_pos as f32 / self.step_size as u8 as f32 / STEPS_PER_REVOLUTION as f32 * 360.
+    }
+
+    /// The angle of the target in degrees from start.
+    pub fn target_angle(&self) -> f32 {
+        self.target_pos as f32 / self.steps_per_degree()
+    }
+
+    /// The time in seconds it takes to reach the target at the current velocity.
+    pub fn time_left(&self) -> f32 {
+        let velocity = self.velocity.max(MIN_RAMP_VELOCITY);
+        self.remaining_steps() as f32 / (velocity * self.steps_per_degree())
+    }
+
+    /// Recalibrate the stepper by giving it it's current angle in degrees.
+    pub fn calib_real_angle(&mut self, angle: f32) {
+        let real_pos =
+            (self.step_size as u8 as f32 * (angle / 360. * STEPS_PER_REVOLUTION as f32)) as i64;
+        self.cur_pos = real_pos;
+        self.target_pos = real_pos;
+    }
+
+    /// Adds `error` degrees to the counted position without moving, for when the stepper
+    /// has lost steps. The target stays where it is.
+    pub fn correct_angle(&mut self, error: f32) {
+        self.cur_pos += libm::roundf(error * self.steps_per_degree()) as i64;
+    }
+
+    /// Goto angles in degrees.
+    pub fn goto_angle(&mut self, angle: f32) {
+        self.goto_position(
+            (self.step_size as u8 as f32 * (angle / 360. * STEPS_PER_REVOLUTION as f32)) as i64,
+        );
+    }
+
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.cur_direction = direction;
+        match direction {
+            Direction::Clockwise => {
+                let _ = self.dir_pin.set_high();
+            }
+            Direction::CounterClockwise => {
+                let _ = self.dir_pin.set_low();
+            }
+        }
+    }
+
+    /// Sets the target, kept within the travel limits.
+    pub fn goto_position(&mut self, position: i64) {
+        self.target_pos = position.clamp(self.min_pos, self.max_pos);
+    }
+
+    /// Keeps the targets of [`Stepper::goto_angle`] between the angles in degrees.
+    pub fn set_travel_limits(&mut self, min_angle: f32, max_angle: f32) {
+        self.min_pos = libm::roundf(min_angle * self.steps_per_degree()) as i64;
+        self.max_pos = libm::roundf(max_angle * self.steps_per_degree()) as i64;
+    }
+
+    /// Stops at the current position, without slowing down.
+    pub fn halt(&mut self) {
+        self.target_pos = self.cur_pos;
+        self.velocity = 0.0;
+        self.ramp = None;
+    }
+
+    /// The number of steps left to the target.
+    pub fn remaining_steps(&self) -> i64 {
+        (self.target_pos - self.cur_pos).abs()
+    }
+
+    /// The current velocity towards the target in degrees per second, which is zero if
+    /// the stepper is moving away from it.
+    pub fn velocity_towards_target(&self) -> f32 {
+        let forward = self.target_pos >= self.cur_pos;
+        let moving_forward = self.cur_direction == self.positive_direction;
+        if forward == moving_forward {
+            self.velocity
+        } else {
+            0.0
+        }
+    }
+
+    pub fn cruise_velocity(&self) -> f32 {
+        self.cruise_velocity
+    }
+
+    /// The number of steps it takes to ramp down to zero in `duration` seconds.
+    pub fn stopping_distance(&self, duration: f32) -> f32 {
+        // The average velocity of both profiles is half of the start velocity.
+        self.velocity * duration / 2.0 * self.steps_per_degree()
+    }
+
+    /// Changes the velocity to `velocity` degrees per second over `duration` seconds.
+    /// The steppers of a movement must ramp with the same duration and profile to stay
+    /// synchronized.
+    pub fn ramp_to(&mut self, velocity: f32, duration: f32, profile: Profile) {
+        let to = libm::fabsf(velocity).min(MAX_VELOCITY);
+        let from = self.velocity_towards_target();
+        self.cruise_velocity = to;
+        self.step_time_us = self.step_time(from.max(MIN_RAMP_VELOCITY));
+        self.ramp = Some(Ramp {
+            from,
+            to,
+            start_us: None,
+            duration_us: (duration * 1e6) as u32,
+            profile,
+        });
+    }
+
+    fn steps_per_degree(&self) -> f32 {
+        self.step_size as u8 as f32 * STEPS_PER_REVOLUTION as f32 / 360.0
+    }
+
+    /// Follows the ramp and returns the velocity to step with.
+    fn ramp_velocity(&mut self, cur_time: u32) -> f32 {
+        let Some(ramp) = &mut self.ramp else {
+            return self.velocity;
+        };
+        let start_us = *ramp.start_us.get_or_insert(cur_time);
+        let elapsed = cur_time.wrapping_sub(start_us);
+        if elapsed >= ramp.duration_us {
+            self.velocity = ramp.to;
+            self.ramp = None;
+        } else {
+            let t = elapsed as f32 / ramp.duration_us as f32;
+            self.velocity = ramp.from + (ramp.to - ramp.from) * ramp.profile.shape(t);
+        }
+        self.velocity.max(MIN_RAMP_VELOCITY)
+    }
+
+    pub fn is_at_target_margin(&self, margin: i64) -> bool {
+        (self.cur_pos - self.target_pos).abs() <= margin
+    }
+
+    /// Like [`Stepper::is_at_target_margin`] with the margin in degrees, so that it
+    /// stays the same whatever the step size.
+    pub fn is_at_target_within(&self, margin: f32) -> bool {
+        self.is_at_target_margin(libm::roundf(margin * self.steps_per_degree()) as i64)
+    }
+
+    /// Velocity in degrees per second, always positive. Takes effect at once, see
+    /// [`Stepper::ramp_to`] for a gradual change.
+    pub fn set_velocity(&mut self, velocity: f32) {
+        let mut velocity = libm::fabsf(velocity);
+        if velocity > MAX_VELOCITY {
+            velocity = MAX_VELOCITY;
+        }
+        self.velocity = velocity;
+        self.cruise_velocity = velocity;
+        self.ramp = None;
+        self.step_time_us = self.step_time(velocity);
+    }
+
+    fn step_time(&self, velocity: f32) -> u32 {
+        let steps_per_second = velocity * self.steps_per_degree();
+        (1e6 / steps_per_second) as u32 / 2 // /2 because it waits for switch high and for switch low,
+    }
+
+    pub fn step(&mut self) {
+        if self.step_is_high {
+            let _ = self.step_pin.set_low();
+            self.step_is_high = false;
+            self.cur_pos += if self.cur_direction == self.positive_direction {
+                1
+            } else {
+                -1
+            };
+        } else {
+            let _ = self.step_pin.set_high();
+            self.step_is_high = true;
+        }
+    }
+
+    pub fn run(&mut self, clock: &impl Clock) {
+        if self.target_pos < self.cur_pos {
+            self.set_direction(!self.positive_direction);
+        } else {
+            self.set_direction(self.positive_direction);
+        }
+        if self.enabled && self.target_pos != self.cur_pos {
+            let cur_time = clock.now_us();
+            let elapsed = cur_time.wrapping_sub(self.time_us_last_step);
+            if elapsed >= self.step_time_us {
+                self.step();
+                // A step that is a little late doesn't delay the next one, otherwise the
+                // steppers that step more often would fall behind the others.
+                self.time_us_last_step = if elapsed < 2 * self.step_time_us {
+                    self.time_us_last_step.wrapping_add(self.step_time_us)
+                } else {
+                    cur_time
+                };
+                if self.ramp.is_some() {
+                    let velocity = self.ramp_velocity(cur_time);
+                    self.step_time_us = self.step_time(velocity);
+                }
+            }
+        }
+    }
+}
+
+impl core::ops::Not for Direction {
+    type Output = Direction;
+
+    fn not(self) -> Self::Output {
+        match self {
+            Direction::Clockwise => Direction::CounterClockwise,
+            Direction::CounterClockwise => Direction::Clockwise,
+        }
+    }
+}
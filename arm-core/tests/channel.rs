@@ -0,0 +1,61 @@
+mod common;
+
+use common::channel;
+
+#[test]
+fn values_arrive_in_order() {
+    let (mut tx, mut rx) = channel::<u32, 4>();
+    assert_eq!(rx.recv(), None);
+    tx.send(1).unwrap();
+    tx.send(2).unwrap();
+    assert_eq!(tx.len(), 2);
+    assert_eq!(rx.peek(), Some(1));
+    assert_eq!(rx.recv(), Some(1));
+    assert_eq!(rx.recv(), Some(2));
+    assert_eq!(rx.recv(), None);
+    assert!(tx.is_empty());
+}
+
+#[test]
+fn full_channel_gives_the_value_back() {
+    let (mut tx, mut rx) = channel::<u32, 4>();
+    for i in 0..3 {
+        tx.send(i).unwrap();
+    }
+    assert_eq!(tx.send(3), Err(3));
+    assert_eq!(rx.recv(), Some(0));
+    tx.send(3).unwrap();
+    assert_eq!(rx.len(), 3);
+}
+
+#[test]
+fn wraps_around() {
+    let (mut tx, mut rx) = channel::<u32, 3>();
+    for i in 0..10 {
+        tx.send(i).unwrap();
+        tx.send(i + 100).unwrap();
+        assert_eq!(rx.recv(), Some(i));
+        assert_eq!(rx.recv(), Some(i + 100));
+    }
+}
+
+#[test]
+fn between_threads() {
+    let (mut tx, mut rx) = channel::<u32, 8>();
+    let sender = std::thread::spawn(move || {
+        for i in 0..1000 {
+            let mut value = i;
+            while let Err(rejected) = tx.send(value) {
+                value = rejected;
+            }
+        }
+    });
+    let mut expected = 0;
+    while expected < 1000 {
+        if let Some(value) = rx.recv() {
+            assert_eq!(value, expected);
+            expected += 1;
+        }
+    }
+    sender.join().unwrap();
+}
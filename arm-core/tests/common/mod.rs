@@ -0,0 +1,127 @@
+#![allow(dead_code)]
+
+use std::cell::Cell;
+use std::convert::Infallible;
+use std::rc::Rc;
+
+use arm_core::channel::{Channel, Receiver, Sender};
+use arm_core::stepper::{Direction, StepSize, Stepper};
+use arm_core::Clock;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// A pin whose state can be read by the test through a clone, and that counts its rising
+/// edges.
+#[derive(Clone, Default)]
+pub struct MockPin {
+    high: Rc<Cell<bool>>,
+    rising_edges: Rc<Cell<u32>>,
+}
+
+impl MockPin {
+    pub fn is_set_high(&self) -> bool {
+        self.high.get()
+    }
+
+    pub fn rising_edges(&self) -> u32 {
+        self.rising_edges.get()
+    }
+
+    /// Sets the level of an input pin.
+    pub fn set(&self, high: bool) {
+        self.high.set(high);
+    }
+}
+
+impl OutputPin for MockPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.high.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        if !self.high.get() {
+            self.rising_edges.set(self.rising_edges.get() + 1);
+        }
+        self.high.set(true);
+        Ok(())
+    }
+}
+
+impl InputPin for MockPin {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Infallible> {
+        Ok(self.high.get())
+    }
+
+    fn is_low(&self) -> Result<bool, Infallible> {
+        Ok(!self.high.get())
+    }
+}
+
+/// A clock that moves forward by `tick` microseconds every time it is read, so that
+/// busy waits end.
+#[derive(Clone)]
+pub struct SimClock {
+    now: Rc<Cell<u32>>,
+    tick: u32,
+    /// Called with the time on every read.
+    on_read: Rc<dyn Fn(u32)>,
+}
+
+impl SimClock {
+    pub fn new(tick: u32) -> Self {
+        SimClock {
+            now: Rc::default(),
+            tick,
+            on_read: Rc::new(|_| {}),
+        }
+    }
+
+    /// Calls `f` with the time on every read.
+    pub fn on_read(mut self, f: impl Fn(u32) + 'static) -> Self {
+        self.on_read = Rc::new(f);
+        self
+    }
+
+    pub fn now(&self) -> u32 {
+        self.now.get()
+    }
+
+    pub fn advance(&self, us: u32) {
+        self.now.set(self.now.get().wrapping_add(us));
+    }
+}
+
+impl Clock for SimClock {
+    fn now_us(&self) -> u32 {
+        let now = self.now.get();
+        self.now.set(now.wrapping_add(self.tick));
+        (self.on_read)(now);
+        now
+    }
+}
+
+/// A stepper and its step and direction pins.
+pub fn stepper(step_size: StepSize) -> (Stepper<MockPin>, MockPin, MockPin) {
+    let step = MockPin::default();
+    let dir = MockPin::default();
+    let stepper = Stepper::new(
+        step.clone(),
+        dir.clone(),
+        step_size,
+        Direction::Clockwise,
+        None,
+        None,
+    );
+    (stepper, step, dir)
+}
+
+/// Makes a channel that lives for the rest of the test.
+pub fn channel<T: Copy, const N: usize>() -> (Sender<T, N>, Receiver<T, N>) {
+    let channel = Box::leak(Box::new(Channel::new()));
+    // Safety: the channel was just made.
+    unsafe { channel.split() }
+}
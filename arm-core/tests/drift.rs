@@ -0,0 +1,72 @@
+use arm_core::drift::{angle_error, Drift, DriftMonitor};
+
+#[test]
+fn errors_wrap_around() {
+    assert_eq!(angle_error(10.0, 5.0), 5.0);
+    assert_eq!(angle_error(5.0, 10.0), -5.0);
+    assert_eq!(angle_error(1.0, 359.0), 2.0);
+    assert_eq!(angle_error(359.0, 1.0), -2.0);
+    assert_eq!(angle_error(10.0, 730.0), 0.0);
+    assert_eq!(angle_error(350.0, -20.0), 10.0);
+}
+
+#[test]
+fn reports_an_error_once() {
+    let mut monitor = DriftMonitor::default();
+    assert_eq!(monitor.check((90.0, 45.0), (90.5, 45.0), 2.0, 0.0), None);
+    let drift = Drift {
+        bottom: 3.0,
+        top: 0.0,
+        correct: false,
+    };
+    assert_eq!(
+        monitor.check((93.0, 45.0), (90.0, 45.0), 2.0, 0.0),
+        Some(drift)
+    );
+    assert_eq!(monitor.check((93.0, 45.0), (90.0, 45.0), 2.0, 0.0), None);
+    // Still above half of the limit.
+    assert_eq!(monitor.check((91.5, 45.0), (90.0, 45.0), 2.0, 0.0), None);
+    assert_eq!(monitor.check((93.0, 45.0), (90.0, 45.0), 2.0, 0.0), None);
+
+    assert_eq!(monitor.check((90.5, 45.0), (90.0, 45.0), 2.0, 0.0), None);
+    assert_eq!(
+        monitor.check((93.0, 45.0), (90.0, 45.0), 2.0, 0.0),
+        Some(drift)
+    );
+
+    monitor.reset();
+    assert_eq!(
+        monitor.check((93.0, 45.0), (90.0, 45.0), 2.0, 0.0),
+        Some(drift)
+    );
+}
+
+#[test]
+fn large_errors_are_corrected() {
+    let mut monitor = DriftMonitor::default();
+    assert_eq!(
+        monitor.check((90.0, 40.0), (90.0, 45.0), 2.0, 4.0),
+        Some(Drift {
+            bottom: 0.0,
+            top: -5.0,
+            correct: true
+        })
+    );
+    // Reported again, the correction may not have been made.
+    assert_eq!(
+        monitor.check((90.0, 40.0), (90.0, 45.0), 2.0, 4.0),
+        Some(Drift {
+            bottom: 0.0,
+            top: -5.0,
+            correct: true
+        })
+    );
+    assert_eq!(
+        monitor.check((90.0, 42.0), (90.0, 45.0), 2.0, 4.0),
+        Some(Drift {
+            bottom: 0.0,
+            top: -3.0,
+            correct: false
+        })
+    );
+}
@@ -0,0 +1,43 @@
+use arm_core::joints::Ratios;
+use arm_core::sensor::{bottom_arm_angle, top_arm_angle};
+
+const RATIOS: Ratios = Ratios {
+    bottom: 22.95,
+    top: 3.3,
+    sideways: 10_000.0,
+};
+
+fn assert_close(a: f32, b: f32) {
+    assert!((a - b).abs() < 1e-3, "{a} != {b}");
+}
+
+#[test]
+fn round_trip() {
+    let [bottom, top, sideways] = RATIOS.to_steppers(30.0, 120.0, 0.2);
+    let (a1, a2, sd) = RATIOS.from_steppers([bottom, top, sideways]);
+    assert_close(a1, 30.0);
+    assert_close(a2, 120.0);
+    assert_close(sd, 0.2);
+}
+
+#[test]
+fn bottom_arm_turns_the_top_stepper() {
+    // The top arm keeps its angle, so its stepper has to turn with the bottom arm.
+    let [bottom, top, sideways] = RATIOS.to_steppers(10.0, 0.0, 0.0);
+    assert_close(bottom, 229.5);
+    assert_close(top, 10.0);
+    assert_close(sideways, 0.0);
+
+    // Turning only the bottom stepper turns the top arm the other way.
+    let (a1, a2, _) = RATIOS.from_steppers([229.5, 0.0, 0.0]);
+    assert_close(a1, 10.0);
+    assert_close(a2, -10.0 / 3.3);
+}
+
+#[test]
+fn sensor_angles_wrap() {
+    assert_close(bottom_arm_angle(-100.0, 90.0), 350.0);
+    assert_close(bottom_arm_angle(10.0, 90.0), 100.0);
+    assert_close(top_arm_angle(0.0, 2.0), 268.0);
+    assert_close(top_arm_angle(-150.0, 2.0), 58.0);
+}
@@ -0,0 +1,223 @@
+mod common;
+
+use arm_core::channel::{Receiver, Sender};
+use arm_core::joints::Ratios;
+use arm_core::motion::{Limits, Motion, Movement, Request, EVENT_CAPACITY, QUEUE_CAPACITY};
+use arm_core::stepper::{Profile, StepSize};
+use common::{channel, stepper, MockPin, SimClock};
+use robby_fischer::{Axis, Event, MotionState};
+
+struct Sim {
+    motion: Motion<MockPin, MockPin, SimClock>,
+    movements: Sender<Movement, QUEUE_CAPACITY>,
+    events: Receiver<Event, EVENT_CAPACITY>,
+    clock: SimClock,
+    button: MockPin,
+}
+
+impl Sim {
+    fn new() -> Self {
+        Sim::with_travel([(-3600.0, 3600.0); 3])
+    }
+
+    fn with_travel(travel: [(f32, f32); 3]) -> Self {
+        let (movements, movement_receiver) = channel();
+        let (event_sender, events) = channel();
+        let clock = SimClock::new(1);
+        let button = MockPin::default();
+        button.set(true);
+        let motion = Motion::new(
+            [0; 3].map(|_| stepper(StepSize::DIV16).0),
+            button.clone(),
+            clock.clone(),
+            Limits {
+                max_speeds: [360.0; 3],
+                max_accels: [100_000.0; 3],
+                profile: Profile::Trapezoidal,
+                travel,
+            },
+            movement_receiver,
+            event_sender,
+        );
+        Sim {
+            motion,
+            movements,
+            events,
+            clock,
+            button,
+        }
+    }
+
+    fn queue(&mut self, bottom: f32, top: f32, sideways: f32) {
+        self.movements.send((bottom, top, sideways, 1.0)).unwrap();
+    }
+
+    /// Runs until `done` or panics after ten simulated seconds.
+    fn run_until(&mut self, mut done: impl FnMut(&mut Self) -> bool) {
+        while !done(self) {
+            assert!(self.clock.now() < 10_000_000, "timed out");
+            self.motion.run();
+            // Time passes even if no stepper reads the clock.
+            self.clock.advance(10);
+        }
+    }
+
+    fn run_for(&mut self, us: u32) {
+        let end = self.clock.now() + us;
+        self.run_until(|sim| sim.clock.now() >= end);
+    }
+
+    fn events(&mut self) -> Vec<Event> {
+        std::iter::from_fn(|| self.events.recv()).collect()
+    }
+}
+
+#[test]
+fn runs_the_queue() {
+    let mut sim = Sim::new();
+    assert_eq!(sim.motion.state(), MotionState::Idle);
+    sim.queue(90.0, 45.0, 180.0);
+    sim.queue(0.0, 0.0, 0.0);
+    sim.motion.run();
+    assert_eq!(sim.motion.state(), MotionState::Moving);
+    sim.run_until(|sim| sim.motion.state() == MotionState::Idle);
+    // Idle is within a few microsteps of the target.
+    sim.run_for(10_000);
+    assert_eq!(sim.motion.angles(), [0.0; 3]);
+    assert_eq!(
+        sim.events(),
+        [
+            Event::SegmentStarted(1),
+            Event::SegmentStarted(0),
+            Event::QueueDrained
+        ]
+    );
+    assert_eq!(sim.motion.dequeued(), 2);
+}
+
+#[test]
+fn steppers_arrive_together() {
+    let mut sim = Sim::new();
+    sim.queue(36.0, 90.0, 180.0);
+    sim.motion.run();
+    let mut arrived = [None; 3];
+    sim.run_until(|sim| {
+        let steppers = [
+            &sim.motion.bottom_arm_stepper,
+            &sim.motion.top_arm_stepper,
+            &sim.motion.sideways_stepper,
+        ];
+        for (arrived, stepper) in arrived.iter_mut().zip(steppers) {
+            if arrived.is_none() && stepper.is_at_target_margin(0) {
+                *arrived = Some(sim.clock.now());
+            }
+        }
+        arrived.iter().all(Option::is_some)
+    });
+    let [bottom, top, sideways] = arrived.map(|time| time.unwrap() as f32 / 1e6);
+    // Half a second for the sideways stepper at full speed.
+    assert!((sideways - 0.5).abs() < 0.05, "{sideways}");
+    assert!((bottom - sideways).abs() < 0.02, "{bottom} {sideways}");
+    assert!((top - sideways).abs() < 0.02, "{top} {sideways}");
+}
+
+#[test]
+fn pause_and_resume() {
+    let mut sim = Sim::new();
+    sim.queue(180.0, 0.0, 0.0);
+    sim.run_for(100_000);
+    sim.motion.handle(Request::Pause).unwrap();
+    assert_eq!(sim.motion.state(), MotionState::Paused);
+    let [paused_at, _, _] = sim.motion.angles();
+    assert!(paused_at > 0.0 && paused_at < 180.0);
+    sim.run_for(100_000);
+    assert_eq!(sim.motion.angles()[0], paused_at);
+
+    sim.motion.handle(Request::Resume).unwrap();
+    sim.run_until(|sim| sim.motion.state() == MotionState::Idle);
+    sim.run_for(10_000);
+    assert_eq!(sim.motion.angles(), [180.0, 0.0, 0.0]);
+    assert_eq!(
+        sim.events(),
+        [
+            Event::SegmentStarted(0),
+            Event::Paused,
+            Event::Resumed,
+            Event::QueueDrained
+        ]
+    );
+}
+
+#[test]
+fn step_size_change_while_paused() {
+    let mut sim = Sim::new();
+    // Half a full step past 180 degrees, an odd number of microsteps.
+    sim.queue(180.9, -180.9, 0.0);
+    sim.run_for(100_000);
+    sim.motion.handle(Request::Pause).unwrap();
+    sim.motion
+        .handle(Request::SetStepSize(Axis::Bottom, StepSize::DIV1))
+        .unwrap();
+    sim.motion
+        .handle(Request::SetStepSize(Axis::Top, StepSize::DIV1))
+        .unwrap();
+
+    sim.motion.handle(Request::Resume).unwrap();
+    sim.run_until(|sim| sim.motion.state() == MotionState::Idle);
+    sim.run_for(10_000);
+    // Rounded away from zero like the positions of the steppers.
+    let [bottom, top, _] = sim.motion.angles();
+    assert!((bottom - 181.8).abs() < 0.001, "{bottom}");
+    assert!((top + 181.8).abs() < 0.001, "{top}");
+    assert!(sim.motion.bottom_arm_stepper.is_at_target_margin(0));
+    assert!(sim.motion.top_arm_stepper.is_at_target_margin(0));
+}
+
+#[test]
+fn stop_clears_the_queue() {
+    let mut sim = Sim::new();
+    sim.queue(180.0, 0.0, 0.0);
+    sim.queue(0.0, 0.0, 0.0);
+    sim.queue(90.0, 0.0, 0.0);
+    sim.run_for(100_000);
+    sim.motion.handle(Request::Stop).unwrap();
+    assert!(sim.movements.is_empty());
+    // The cleared movements count too.
+    assert_eq!(sim.motion.dequeued(), 3);
+    assert_eq!(sim.motion.state(), MotionState::Idle);
+    let stopped_at = sim.motion.angles();
+    sim.run_for(100_000);
+    assert_eq!(sim.motion.angles(), stopped_at);
+    assert_eq!(sim.events(), [Event::SegmentStarted(2), Event::Stopped]);
+}
+
+#[test]
+fn limit_switch() {
+    let mut sim = Sim::new();
+    sim.motion.run();
+    sim.button.set(false);
+    sim.motion.run();
+    sim.motion.run();
+    assert_eq!(sim.events(), [Event::LimitSwitch]);
+}
+
+#[test]
+fn top_travel_includes_the_bottom_arm() {
+    let ratios = Ratios {
+        bottom: 2.0,
+        top: 2.0,
+        sideways: 100.0,
+    };
+    let mut sim = Sim::with_travel(ratios.travel((-20.0, 200.0), (-10.0, 190.0), (0.0, 0.6)));
+    // Both arm angles are within their travel, but the top stepper goes further than
+    // the top arm travel alone.
+    let [bottom, top, sideways] = ratios.to_steppers(150.0, 150.0, 0.3);
+    sim.queue(bottom, top, sideways);
+    sim.run_until(|sim| sim.events().contains(&Event::QueueDrained));
+    sim.run_for(10_000);
+    let (bottom, top, sideways) = ratios.from_steppers(sim.motion.angles());
+    // Clamped to the top arm travel, the top arm would end up at 115 degrees.
+    assert!((bottom - 150.0).abs() < 0.1, "{bottom}");
+    assert!((top - 150.0).abs() < 0.1, "{top}");
+    assert!((sideways - 0.3).abs() < 0.001, "{sideways}");
+}
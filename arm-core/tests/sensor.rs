@@ -0,0 +1,81 @@
+use std::f32::consts::PI;
+
+use arm_core::{
+    drift::angle_error,
+    sensor::{
+        bottom_arm_angle, bottom_sensor_angle, filter, top_arm_angle, top_sensor_angle,
+        Calibration, TABLE_LEN,
+    },
+};
+
+/// The field of a magnet that is off center over a tilted sensor, at the angle plus
+/// `error(angle)` in degrees.
+fn field(angle: f32, error: impl Fn(f32) -> f32) -> (f32, f32, f32) {
+    let radians = (angle + error(angle)) * PI / 180.0;
+    let (sin, cos) = radians.sin_cos();
+    (
+        angle,
+        300.0 + 1200.0 * cos - 100.0 * sin,
+        -200.0 + 80.0 * cos + 900.0 * sin,
+    )
+}
+
+#[test]
+fn fit_corrects_an_offset_ellipse() {
+    // Like the joints, which don't go all the way around.
+    let samples: Vec<_> = (0..60)
+        .map(|i| field(-30.0 + i as f32 * 2.0, |_| 0.0))
+        .collect();
+    let calibration = Calibration::fit(&samples).unwrap();
+    for &(angle, x, y) in &samples {
+        assert!(angle_error(calibration.angle(x, y), angle).abs() < 0.05);
+    }
+    assert!(calibration.rms_error(&samples) < 0.05);
+    assert!(Calibration::NONE.rms_error(&samples) > 1.0);
+}
+
+#[test]
+fn fit_needs_spread_out_angles() {
+    let samples = [field(10.0, |_| 0.0); 20];
+    assert_eq!(Calibration::fit(&samples), None);
+}
+
+#[test]
+fn table_corrects_what_the_ellipse_cannot() {
+    let error = |angle: f32| 2.0 * (3.0 * angle * PI / 180.0).sin();
+    let samples: Vec<_> = (0..180)
+        .map(|i| field(-180.0 + i as f32 * 2.0, error))
+        .collect();
+    let calibration = Calibration::fit(&samples).unwrap();
+    let ellipse_only = Calibration {
+        table: [0.0; TABLE_LEN],
+        ..calibration
+    };
+    assert!(ellipse_only.rms_error(&samples) > 1.0);
+    assert!(calibration.rms_error(&samples) < 0.3);
+}
+
+#[test]
+fn filter_leaves_out_outliers() {
+    let reading = filter(&mut [10.0, 10.2, 9.8, 30.0, 10.0]);
+    assert!((reading.angle - 10.0).abs() < 0.001);
+    assert_eq!(reading.samples, 4);
+    assert!(reading.uncertainty > 0.0 && reading.uncertainty < 0.2);
+}
+
+#[test]
+fn filter_averages_across_180() {
+    let reading = filter(&mut [179.0, -179.0, 179.5, -179.5, 180.0]);
+    assert!(angle_error(reading.angle, 180.0).abs() < 0.001);
+    assert_eq!(reading.samples, 5);
+}
+
+#[test]
+fn sensor_angles_invert_arm_angles() {
+    for angle in (0..36).map(|i| i as f32 * 10.0) {
+        let bottom = bottom_arm_angle(bottom_sensor_angle(angle, 90.0), 90.0);
+        let top = top_arm_angle(top_sensor_angle(angle, 2.0), 2.0);
+        assert!(angle_error(bottom, angle).abs() < 0.001);
+        assert!(angle_error(top, angle).abs() < 0.001);
+    }
+}
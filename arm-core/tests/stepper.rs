@@ -0,0 +1,207 @@
+mod common;
+
+use arm_core::stepper::{HomingError, Profile, StepSize};
+use common::{stepper, MockPin, SimClock};
+
+#[test]
+fn steps_to_the_target() {
+    let (mut stepper, step, dir) = stepper(StepSize::DIV1);
+    let clock = SimClock::new(100);
+    stepper.set_velocity(360.0);
+    // 1.8 degrees per full step.
+    stepper.goto_angle(90.0);
+    while !stepper.is_at_target_margin(0) {
+        stepper.run(&clock);
+    }
+    assert_eq!(step.rising_edges(), 50);
+    assert!(dir.is_set_high());
+    assert_eq!(stepper.get_angle(), 90.0);
+
+    stepper.goto_angle(45.0);
+    while !stepper.is_at_target_margin(0) {
+        stepper.run(&clock);
+    }
+    assert_eq!(step.rising_edges(), 75);
+    assert!(!dir.is_set_high());
+    assert_eq!(stepper.get_angle(), 45.0);
+}
+
+#[test]
+fn steps_at_the_velocity() {
+    let (mut stepper, _, _) = stepper(StepSize::DIV1);
+    let clock = SimClock::new(10);
+    stepper.set_velocity(360.0);
+    stepper.goto_angle(360.0);
+    while !stepper.is_at_target_margin(0) {
+        stepper.run(&clock);
+    }
+    // One turn at one turn per second.
+    let seconds = clock.now() as f32 / 1e6;
+    assert!((seconds - 1.0).abs() < 0.01, "{seconds}");
+}
+
+#[test]
+fn calib_real_angle_stops_the_stepper() {
+    let (mut stepper, step, _) = stepper(StepSize::DIV1);
+    let clock = SimClock::new(100);
+    stepper.goto_angle(90.0);
+    stepper.calib_real_angle(180.0);
+    assert!(stepper.is_at_target_margin(0));
+    assert_eq!(stepper.get_angle(), 180.0);
+    stepper.run(&clock);
+    assert_eq!(step.rising_edges(), 0);
+}
+
+#[test]
+fn correct_angle_keeps_the_target() {
+    let (mut stepper, step, _) = stepper(StepSize::DIV1);
+    let clock = SimClock::new(100);
+    stepper.set_velocity(360.0);
+    stepper.goto_angle(90.0);
+    // The stepper lost 5 steps backwards, which it has to make up for.
+    stepper.correct_angle(-9.0);
+    assert_eq!(stepper.get_angle(), -9.0);
+    while !stepper.is_at_target_margin(0) {
+        stepper.run(&clock);
+    }
+    assert_eq!(step.rising_edges(), 55);
+    assert_eq!(stepper.get_angle(), 90.0);
+}
+
+#[test]
+fn ramps_to_the_velocity() {
+    let (mut stepper, _, _) = stepper(StepSize::DIV1);
+    let clock = SimClock::new(10);
+    stepper.goto_angle(10_000.0);
+    stepper.set_velocity(360.0);
+    stepper.ramp_to(720.0, 0.5, Profile::Trapezoidal);
+    assert_eq!(stepper.velocity_towards_target(), 360.0);
+    while clock.now() < 250_000 {
+        stepper.run(&clock);
+    }
+    let halfway = stepper.velocity_towards_target();
+    assert!((halfway - 540.0).abs() < 20.0, "{halfway}");
+    while clock.now() < 600_000 {
+        stepper.run(&clock);
+    }
+    assert_eq!(stepper.velocity_towards_target(), 720.0);
+    assert_eq!(stepper.cruise_velocity(), 720.0);
+}
+
+#[test]
+fn calibrate_stops_where_the_button_is_released() {
+    let (mut stepper, step, dir) = stepper(StepSize::DIV1);
+    stepper.calib_real_angle(90.0);
+    let button = MockPin::default();
+    button.set(true);
+    // The button is pressed after 10 steps back and released after 3 steps forward.
+    let pin = button.clone();
+    let clock =
+        SimClock::new(10).on_read(move |_| pin.set(!(10..13).contains(&step.rising_edges())));
+    assert_eq!(
+        stepper.calibrate(&mut button.clone(), 20.0, 500.0, 360.0, 1_000_000, &clock),
+        Ok(())
+    );
+    assert!(dir.is_set_high());
+    assert!(button.is_set_high());
+    assert_eq!(stepper.cur_pos, 0);
+}
+
+#[test]
+fn calibrate_gives_up_without_the_button() {
+    let (mut stepper, step, _) = stepper(StepSize::DIV1);
+    stepper.calib_real_angle(90.0);
+    let button = MockPin::default();
+    button.set(true);
+    let clock = SimClock::new(10);
+    assert_eq!(
+        stepper.calibrate(&mut button.clone(), 20.0, 500.0, 18.0, 1_000_000, &clock),
+        Err(HomingError::MaxTravel)
+    );
+    assert_eq!(step.rising_edges(), 10);
+    // The position is still counted from the last calibration.
+    assert_eq!(stepper.cur_pos, 40);
+    assert_eq!(stepper.target_pos, 40);
+}
+
+#[test]
+fn calibrate_times_out() {
+    let (mut stepper, _, _) = stepper(StepSize::DIV1);
+    let button = MockPin::default();
+    button.set(true);
+    let clock = SimClock::new(10);
+    assert_eq!(
+        stepper.calibrate(&mut button.clone(), 20.0, 500.0, 3600.0, 50_000, &clock),
+        Err(HomingError::Timeout)
+    );
+    assert!(clock.now() >= 50_000);
+    assert!(clock.now() < 60_000);
+}
+
+#[test]
+fn targets_stay_within_the_travel_limits() {
+    let (mut stepper, _, _) = stepper(StepSize::DIV1);
+    stepper.set_travel_limits(-90.0, 180.0);
+    // 1.8 degrees per full step.
+    stepper.goto_angle(270.0);
+    assert_eq!(stepper.target_pos, 100);
+    stepper.goto_angle(-180.0);
+    assert_eq!(stepper.target_pos, -50);
+    stepper.goto_angle(45.0);
+    assert_eq!(stepper.target_pos, 25);
+}
+
+#[test]
+fn step_size_keeps_the_angle() {
+    let (mut stepper, step, _) = stepper(StepSize::DIV16);
+    let clock = SimClock::new(100);
+    stepper.set_velocity(360.0);
+    stepper.calib_real_angle(9.0);
+    stepper.goto_angle(90.0);
+
+    stepper.set_step_size(StepSize::DIV2);
+    // 0.9 degrees per half step.
+    assert_eq!(stepper.cur_pos, 10);
+    assert_eq!(stepper.target_pos, 100);
+    while !stepper.is_at_target_margin(0) {
+        stepper.run(&clock);
+    }
+    assert_eq!(step.rising_edges(), 90);
+}
+
+#[test]
+fn target_margin_is_in_degrees() {
+    let (mut stepper, _, _) = stepper(StepSize::DIV16);
+    // 3 microsteps at 80/9 microsteps per degree.
+    stepper.goto_angle(0.36);
+    assert_eq!(stepper.target_pos, 3);
+    assert!(stepper.is_at_target_within(0.34));
+    assert!(!stepper.is_at_target_within(0.2));
+
+    stepper.set_step_size(StepSize::DIV1);
+    stepper.goto_angle(1.8);
+    assert!(!stepper.is_at_target_within(0.34));
+    assert!(stepper.is_at_target_within(1.8));
+}
+
+#[test]
+fn disabled_stepper_stands_still() {
+    let (mut stepper, step, _) = stepper(StepSize::DIV1);
+    let clock = SimClock::new(100);
+    stepper.set_velocity(360.0);
+    stepper.goto_angle(90.0);
+    stepper.set_enabled(false);
+    assert!(stepper.is_at_target_margin(0));
+
+    stepper.goto_angle(180.0);
+    for _ in 0..1000 {
+        stepper.run(&clock);
+    }
+    assert_eq!(step.rising_edges(), 0);
+
+    stepper.set_enabled(true);
+    while !stepper.is_at_target_margin(0) {
+        stepper.run(&clock);
+    }
+    assert_eq!(step.rising_edges(), 100);
+}
@@ -0,0 +1,66 @@
+//! The angle sensor calibrations, see [`Calibration`]. They are kept in the sector before
+//! the saved position, see [`crate::position`].
+
+use alloc::vec;
+use arm_core::sensor::{Calibration, TABLE_LEN};
+use burktelefon::frame::{crc16, Field, Reader, Writer};
+
+use crate::params::{FLASH_SIZE, SECTOR_SIZE, XIP_BASE};
+
+/// The offset of the sector from the start of the flash.
+const CALIBRATION_OFFSET: u32 = FLASH_SIZE - 3 * SECTOR_SIZE;
+
+/// Marks a sector that has been written by [`save`], "RFC1".
+const MAGIC: u32 = 0x3143_4652;
+/// The magic number and the center, the inverse and the table of two calibrations.
+const LEN: usize = 4 + 2 * (2 + 4 + TABLE_LEN) * 4;
+
+/// The bottom and top sensor calibrations, if they have been saved.
+pub fn load() -> Option<[Calibration; 2]> {
+    // Safety: the flash is always mapped and only written by `save`.
+    let sector = unsafe {
+        core::slice::from_raw_parts(
+            (XIP_BASE + CALIBRATION_OFFSET) as *const u8,
+            SECTOR_SIZE as usize,
+        )
+    };
+    let crc = u16::from_le_bytes(sector[LEN..LEN + 2].try_into().ok()?);
+    if crc16(&sector[..LEN]) != crc {
+        return None;
+    }
+    let mut r = Reader::new(sector);
+    if u32::read(&mut r).ok()? != MAGIC {
+        return None;
+    }
+    let mut calibrations = [Calibration::NONE; 2];
+    for calibration in &mut calibrations {
+        let mut values = [0.0; 6];
+        for value in values.iter_mut().chain(&mut calibration.table) {
+            *value = f32::read(&mut r).ok()?;
+        }
+        let [x, y, a, b, c, d] = values;
+        calibration.center = (x, y);
+        calibration.inverse = [[a, b], [c, d]];
+    }
+    Some(calibrations)
+}
+
+/// Writes the bottom and top sensor calibrations to flash.
+pub fn save(calibrations: &[Calibration; 2]) {
+    let mut sector = vec![0xff; SECTOR_SIZE as usize];
+    let mut w = Writer::new(&mut sector);
+    let _ = MAGIC.write(&mut w);
+    for calibration in calibrations {
+        let [[a, b], [c, d]] = calibration.inverse;
+        let values = [calibration.center.0, calibration.center.1, a, b, c, d];
+        for value in values.iter().chain(&calibration.table) {
+            let _ = value.write(&mut w);
+        }
+    }
+    let crc = crc16(w.written());
+    let _ = crc.write(&mut w);
+    // Nothing may run from flash while it is written, which includes the USB interrupt.
+    cortex_m::interrupt::free(|_| unsafe {
+        rp2040_flash::flash::flash_range_erase_and_program(CALIBRATION_OFFSET, &sector, true);
+    });
+}
@@ -1,6 +1,6 @@
 use core::fmt::Write;
 use core::mem::MaybeUninit;
-use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
 
 use alloc::string::String;
 use alloc::vec::Vec;
@@ -16,6 +16,8 @@ use usbd_serial::SerialPort;
 static mut USB_DEVICE: Option<UsbDevice<UsbBus>> = None;
 static mut USB_BUS: Option<UsbBusAllocator<UsbBus>> = None;
 static mut USB_SERIAL: Option<SerialPort<UsbBus>> = None;
+// A second serial port for the log, so that it doesn't get in the way of the responses.
+static mut USB_LOG: Option<SerialPort<UsbBus>> = None;
 
 // Read buffer
 static mut READ_BUFFER: [u8; 4096] = [0; 4096];
@@ -24,6 +26,9 @@ static READ_AVAILABLE: AtomicUsize = AtomicUsize::new(0);
 // Set to `true` when writing should retry.
 static WRITE_AVAILABLE: AtomicBool = AtomicBool::new(true);
 
+// The most detailed `Level` that is logged.
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
 // The heap allocator.
 #[global_allocator]
 static HEAP: Heap = Heap::empty();
@@ -58,16 +63,20 @@ pub unsafe fn start_serial(
         regs, dpram, clock, true, resets,
     )));
 
-    // Set up the serial port.
+    // Set up the serial ports, the commands are on the first one.
     USB_SERIAL = Some(SerialPort::new(bus));
+    USB_LOG = Some(SerialPort::new(bus));
 
-    // Create a USB device (with a fake ID and info)
+    // Create a USB device (with a fake ID and info). The class says that the interfaces
+    // are grouped by association descriptors, one group for each serial port.
     USB_DEVICE = Some(
         UsbDeviceBuilder::new(bus, UsbVidPid(0x16c0, 0x27dd))
             .manufacturer("alebe herla")
             .product("robby fischer")
             .serial_number("1972")
-            .device_class(2)
+            .device_class(0xef)
+            .device_sub_class(0x02)
+            .device_protocol(0x01)
             .build(),
     );
 
@@ -81,15 +90,19 @@ pub unsafe fn start_serial(
 unsafe fn USBCTRL_IRQ() {
     let usb_dev = USB_DEVICE.as_mut().unwrap();
     let serial = USB_SERIAL.as_mut().unwrap();
+    let log = USB_LOG.as_mut().unwrap();
 
     // Poll the device, and return if nothing more needs to be done.
-    if !usb_dev.poll(&mut [serial]) {
+    if !usb_dev.poll(&mut [serial, log]) {
         return;
     }
 
     // Tell the writer that it may write again, in case it failed.
     WRITE_AVAILABLE.store(true, Ordering::Relaxed);
 
+    // Nothing is read from the log.
+    _ = log.read(&mut [0; 64]);
+
     // Discard reads if the buffer is full.
     let index = READ_AVAILABLE.load(Ordering::Acquire);
     if index >= READ_BUFFER.len() {
@@ -131,6 +144,53 @@ pub fn serial_write(mut data: &[u8]) {
     }
 }
 
+/// Writes the data to the log serial, see [`log`]. What doesn't fit in its buffer is
+/// dropped, so that a terminal that doesn't read the log can't hold up the arm.
+pub fn log_write(mut data: &[u8]) {
+    cortex_m::interrupt::free(|_| {
+        let log = unsafe { USB_LOG.as_mut().unwrap() };
+        while let Ok(count @ 1..) = log.write(data) {
+            data = &data[count..];
+        }
+    });
+}
+
+/// How important a log message is, see [`log`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+}
+
+/// Logs the messages up to `level` from now on.
+pub fn set_log_level(level: Level) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// True if a message at `level` would be read. Nothing is logged while the log port is
+/// closed, so that the messages aren't formatted for nobody.
+pub fn log_enabled(level: Level) -> bool {
+    level as u8 <= LOG_LEVEL.load(Ordering::Relaxed) && log_connected()
+}
+
+/// True while a terminal has the log port open.
+fn log_connected() -> bool {
+    cortex_m::interrupt::free(|_| unsafe { USB_LOG.as_ref().map_or(false, |log| log.dtr()) })
+}
+
 /// Waits until any data is available and then reads from the serial device. The
 /// closure must return the amount of consumed bytes.
 pub fn serial_read(handler: impl FnOnce(&[u8]) -> usize) {
@@ -160,7 +220,7 @@ pub fn serial_read(handler: impl FnOnce(&[u8]) -> usize) {
 #[cfg(all(not(test), target_os = "none"))]
 #[panic_handler]
 fn panic_handler(info: &core::panic::PanicInfo) -> ! {
-    println!("{info}");
+    log!(Level::Error, "{info}");
     loop {
         core::hint::spin_loop();
     }
@@ -193,8 +253,38 @@ macro_rules! println {
     }};
 }
 
+pub struct LogPrinter;
+impl Write for LogPrinter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        log_write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Writes a line to the log port if `level` is logged, with the milliseconds since
+/// boot and the level in front of it.
+#[allow(unused)]
+macro_rules! log {
+    ($level:expr, $($args:tt)+) => {{
+        let level: $crate::hardware::Level = $level;
+        if $crate::hardware::log_enabled(level) {
+            use $crate::hardware::LogPrinter;
+            use ::core::fmt::Write;
+            write!(
+                LogPrinter,
+                "{:>8} {:<5} ",
+                $crate::motion::now_us() / 1000,
+                level.name()
+            )
+            .unwrap();
+            write!(LogPrinter, $($args)+).unwrap();
+            $crate::hardware::log_write(b"\r\n");
+        }
+    }};
+}
+
 #[allow(unused)]
-pub(super) use {print, println};
+pub(super) use {log, print, println};
 
 /// Tests if any data is available on serial.
 pub fn serial_available() -> bool {
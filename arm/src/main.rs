@@ -3,23 +3,42 @@
 
 extern crate alloc;
 
+mod calibration;
 mod hardware;
-mod stepper;
+mod motion;
+mod params;
+mod position;
 
 use core::{f32, str::FromStr};
 
-use alloc::{
-    collections::VecDeque,
-    string::{String, ToString},
+use alloc::vec::Vec;
+use arm_core::{
+    drift::DriftMonitor,
+    joints::Ratios,
+    motion::{Axis, Limits, Request, QUEUE_CAPACITY},
+    sensor::{
+        bottom_arm_angle, bottom_sensor_angle, top_arm_angle, top_sensor_angle, AngleSensor,
+        Calibration, Reading,
+    },
+    stepper::{Direction, Profile, StepSize, Stepper},
+};
+use burktelefon::{
+    frame::{self, Binary, MAX_FRAME_LEN},
+    text, Codec,
 };
 use cortex_m::delay::Delay;
 use debugless_unwrap::DebuglessUnwrap;
-use embedded_hal::{blocking::delay::DelayMs, blocking::i2c, digital::v2::InputPin, PwmPin};
+use embedded_hal::{
+    blocking::{delay::DelayMs, i2c},
+    digital::v2::InputPin,
+    PwmPin,
+};
 use fugit::RateExtU32;
 use hardware::read_byte;
-use mlx90393::{DigitalFilter, I2CInterface, Magnetometer, OverSamplingRatio};
-use robby_fischer::{Command, Response};
-use rp_pico::hal::{pwm, Timer};
+use motion::MotionHandle;
+use params::Params;
+use robby_fischer::{Command, ErrorCode, Event, MotionState, Param, Response, Segment};
+use rp_pico::hal::{multicore::Multicore, pwm, Timer};
 use rp_pico::hal::{Clock, Sio, I2C};
 use rp_pico::Pins;
 use rp_pico::{
@@ -33,258 +52,732 @@ use rp_pico::{
     pac::CorePeripherals,
     pac::Peripherals,
 };
-use stepper::{Direction, StepSize, Stepper};
 
-use crate::hardware::{println, serial_available};
-
-// const TOP_RATIO: f32 = 66.0 / 21.0; // Stepper angle / arm angle
-const TOP_RATIO: f32 = 66.0 / 20.0; // Why does this work better?
-const BOT_RATIO: f32 = (34.0 / 8.0) * (54.0 / 10.0); // Stepper angle / arm angle
-const SIDEWAYS_DEGREE_PER_M: f32 = 360.0 / (18.0 * 0.002);
-
-const BOT_ARM_MAX_SPEED: f32 = 1200.0;
-const TOP_ARM_MAX_SPEED: f32 = 120.0;
-const SIDEWAYS_MAX_SPEED: f32 = 1600.0;
-
-struct AngleSensor {
-    pub mlx: Magnetometer,
-    address: u8,
-}
-
-impl AngleSensor {
-    pub fn new<WR, E>(i2c: &mut WR, address: u8) -> Result<Self, mlx90393::Error<E>>
-    where
-        WR: i2c::WriteRead<Error = E>,
-    {
-        let mut protocol = I2CInterface { i2c, address };
-        let mut mlx = Magnetometer::default_settings(&mut protocol)?;
-        mlx.set_filter(&mut protocol, DigitalFilter::DF4)?;
-        mlx.set_oversampling_ratio(&mut protocol, OverSamplingRatio::OSR4)?;
-
-        // mlx.
-        Ok(AngleSensor { mlx, address })
-    }
-
-    pub fn get_angle<WR, E>(
-        &mut self,
-        i2c: &mut WR,
-        delay: &mut impl DelayMs<u32>,
-    ) -> Result<f32, mlx90393::Error<E>>
-    where
-        WR: i2c::WriteRead<Error = E>,
-    {
-        let mut protocol = I2CInterface {
-            i2c,
-            address: self.address,
-        };
-        let (_t, x, y, _z) = self.mlx.do_measurement(&mut protocol, delay)?;
-        // println!("{} {}", x, y);
-        let angle = libm::atan2f(y as f32, x as f32);
-        Ok(angle * 180. / core::f32::consts::PI)
-    }
-}
+use crate::hardware::{log, println, serial_available, serial_write, Level};
+
+/// The max number of queued movements, all that fit in the queue to the second core.
+const MAX_QUEUE_LEN: usize = QUEUE_CAPACITY - 1;
+/// The least room sent in one [`Event::Credit`], unless the queue is empty.
+const CREDIT_BATCH: u32 = 8;
+/// How often the angle sensors are compared with the steppers.
+const DRIFT_CHECK_INTERVAL_US: u32 = 250_000;
+/// How long the arm has to stand still before its position is saved.
+const POSITION_SAVE_DELAY_US: u32 = 1_000_000;
+/// The number of angles each arm stops at on its way in [`Arm::calibrate_sensors`], it
+/// stops at them again on its way back.
+const SWEEP_STOPS: usize = 60;
+/// How long the arm is left to stop shaking before its sensor is read.
+const SWEEP_SETTLE_MS: u32 = 50;
 
 struct Arm<S: SliceId, M: SliceMode, C: ChannelId, I> {
     is_sideways_calibrated: bool,
+    /// Set when the arm angles have been read from the angle sensors.
+    is_arm_calibrated: bool,
 
     i2c: I,
     bottom_angle_sensor: AngleSensor,
     top_angle_sensor: AngleSensor,
-
-    bottom_arm_stepper: Stepper,
-
-    top_arm_stepper: Stepper,
-
-    sideways_stepper: Stepper,
-    sideways_button: DynPin,
+    drift: DriftMonitor,
+    last_drift_check: u32,
+    /// Whether the sensors have been read since the arm came to rest, they are only read
+    /// once then so that the blocking reads don't hold up the commands.
+    drift_checked_at_rest: bool,
+
+    /// The steppers run on the second core.
+    motion: MotionHandle,
     chess_button: DynPin,
     chess_button_last_state: bool,
     chess_button_been_pressed: bool,
 
     servo_channel: Channel<S, M, C>,
 
-    movement_buffer: VecDeque<(f32, f32, f32, f32)>,
+    /// The codec of the last received command, responses are sent with the same codec.
+    codec: Codec,
+    /// The sequence id of the last acknowledged command.
+    last_seq: Option<u16>,
+    /// Set by [`Command::Events`].
+    events_enabled: bool,
+    params: Params,
+    /// Set while the flash holds the current position, see [`position`].
+    position_saved: bool,
+    /// When the arm was last seen moving.
+    last_moving: u32,
+    /// The number of dequeued movements that the host has been told about, see
+    /// [`Command::Credit`].
+    credited: u32,
+    /// Whether the bottom, top and sideways drivers are on, see [`Command::EnableDriver`].
+    drivers_enabled: [bool; 3],
 }
 
 impl<S: SliceId, M: SliceMode, I> Arm<S, M, pwm::B, I>
 where
     I: i2c::WriteRead,
 {
-    pub fn calibrate_sideways(&mut self, delay: &mut Delay) {
-        self.sideways_stepper
-            .calibrate(&mut self.sideways_button, 20.0, 500., delay);
-
+    pub fn calibrate_sideways(&mut self) -> Result<(), (ErrorCode, u32)> {
+        self.check_enabled(Axis::Sideways)?;
+        self.before_motion();
+        let max_travel =
+            self.params.get(Param::HomingTravel) * self.params.get(Param::SidewaysDegreePerM);
+        let timeout_us = (self.params.get(Param::HomingTimeout) * 1_000_000.0) as u32;
+        if let Err(error) = self.motion.calibrate_sideways(max_travel, timeout_us) {
+            log!(Level::Warn, "sideways calibration failed: {error:?}");
+            // Where the stepper is is not known anymore.
+            self.is_sideways_calibrated = false;
+            return Err((ErrorCode::Homing, error as u32));
+        }
         self.is_sideways_calibrated = true;
+        self.push_event(Event::SidewaysCalibrated);
+        Ok(())
     }
 
-    pub fn calibrate_arm(&mut self, delay: &mut Delay) -> Option<()> {
-        // Constant error on bottom sensor, might be because the sensor is
-        // not aligned perfectly with the magnet.
+    pub fn calibrate_arm(&mut self, delay: &mut Delay) -> Result<(), (ErrorCode, u32)> {
+        let (a1, a2) = self.read_angles(delay)?;
 
-        let mut a1 = self
+        log!(Level::Debug, "arm angles {} {}", a1.angle, a2.angle);
+        let [bottom, top, _] = self.ratios().to_steppers(a1.angle, a2.angle, 0.0);
+        self.motion.request(Request::CalibrateArm { bottom, top });
+        self.drift.reset();
+        self.is_arm_calibrated = true;
+        self.push_event(Event::ArmCalibrated);
+        Ok(())
+    }
+
+    /// Sweeps each arm between the angles in degrees and fits the calibrations of their
+    /// angle sensors to the angles the steppers count. The counted angles were read from
+    /// the sensors by the last arm calibration, so this corrects how the readings change
+    /// along the sweep but not the offset.
+    fn calibrate_sensors(
+        &mut self,
+        delay: &mut Delay,
+        bottom: (f32, f32),
+        top: (f32, f32),
+    ) -> Result<(), (ErrorCode, u32)> {
+        self.check_calibrated(false, true)?;
+        let sweeps = [(Axis::Bottom, bottom), (Axis::Top, top)];
+        for (axis, (from, to)) in sweeps {
+            self.check_travel(axis, from)?;
+            self.check_travel(axis, to)?;
+        }
+        self.before_motion();
+
+        let mut calibrations = [Calibration::NONE; 2];
+        let mut errors = [0.0; 2];
+        for (i, (axis, (from, to))) in sweeps.into_iter().enumerate() {
+            let samples = self.sweep(delay, axis, from, to)?;
+            let address = match axis {
+                Axis::Bottom => self.bottom_angle_sensor.address,
+                _ => self.top_angle_sensor.address,
+            };
+            calibrations[i] =
+                Calibration::fit(&samples).ok_or((ErrorCode::SensorFit, address as u32))?;
+            errors[i] = calibrations[i].rms_error(&samples);
+        }
+        self.bottom_angle_sensor.calibration = calibrations[0];
+        self.top_angle_sensor.calibration = calibrations[1];
+        self.motion.parked(|| calibration::save(&calibrations));
+
+        // The sensors read differently now.
+        self.calibrate_arm(delay)?;
+        self.push_event(Event::SensorsCalibrated(errors[0], errors[1]));
+        Ok(())
+    }
+
+    /// Moves an arm from `from` to `to` degrees and back, stopping [`SWEEP_STOPS`] times
+    /// each way to measure the field of its sensor. Returns the sensor angles the steppers
+    /// count and the fields, for [`Calibration::fit`].
+    fn sweep(
+        &mut self,
+        delay: &mut Delay,
+        axis: Axis,
+        from: f32,
+        to: f32,
+    ) -> Result<Vec<(f32, f32, f32)>, (ErrorCode, u32)> {
+        let mut samples = Vec::with_capacity(2 * SWEEP_STOPS);
+        for stop in (0..SWEEP_STOPS).chain((0..SWEEP_STOPS).rev()) {
+            self.move_axis(
+                axis,
+                from + (to - from) * stop as f32 / (SWEEP_STOPS - 1) as f32,
+            );
+            while self.motion.state() != MotionState::Idle {
+                core::hint::spin_loop();
+            }
+            delay.delay_ms(SWEEP_SETTLE_MS);
+
+            let (bottom, top, _) = self.ratios().from_steppers(self.motion.angles());
+            let (sensor, angle) = match axis {
+                Axis::Bottom => (
+                    &mut self.bottom_angle_sensor,
+                    bottom_sensor_angle(bottom, self.params.get(Param::BottomAngleOffset)),
+                ),
+                _ => (
+                    &mut self.top_angle_sensor,
+                    top_sensor_angle(top, self.params.get(Param::TopAngleOffset)),
+                ),
+            };
+            let (x, y) = sensor
+                .get_field(&mut self.i2c, delay)
+                .map_err(|_| (ErrorCode::Sensor, sensor.address as u32))?;
+            samples.push((angle, x, y));
+        }
+        Ok(samples)
+    }
+
+    /// Reads the bottom and top arm angles from the angle sensors.
+    fn read_angles(&mut self, delay: &mut Delay) -> Result<(Reading, Reading), (ErrorCode, u32)> {
+        let a1 = self
             .bottom_angle_sensor
             .get_angle(&mut self.i2c, delay)
-            .ok()?;
-        a1 += 90.0;
-        if a1 < 0.0 {
-            a1 += 360.0
-        };
-        let mut a2 = self.top_angle_sensor.get_angle(&mut self.i2c, delay).ok()?;
-        a2 += 2.0;
-        a2 = -a2;
-        a2 -= 90.0;
-        if a2 < 0.0 {
-            a2 += 360.0
-        };
+            .map_err(|_| (ErrorCode::Sensor, self.bottom_angle_sensor.address as u32))?;
+        let a2 = self
+            .top_angle_sensor
+            .get_angle(&mut self.i2c, delay)
+            .map_err(|_| (ErrorCode::Sensor, self.top_angle_sensor.address as u32))?;
+        Ok((
+            Reading {
+                angle: bottom_arm_angle(a1.angle, self.params.get(Param::BottomAngleOffset)),
+                ..a1
+            },
+            Reading {
+                angle: top_arm_angle(a2.angle, self.params.get(Param::TopAngleOffset)),
+                ..a2
+            },
+        ))
+    }
 
-        // println!("{a1} {a2}");
-        self.bottom_arm_stepper.calib_real_angle(a1 * BOT_RATIO);
-        self.top_arm_stepper
-            .calib_real_angle((a2 + a1 / TOP_RATIO) * TOP_RATIO);
-        Some(())
-    }
-
-    pub fn parse_command(&mut self, delay: &mut Delay, line: &str) {
-        if let Ok(command) = Command::from_str(line) {
-            match command {
-                Command::Magnets => {
-                    let mut a1 = self
-                        .bottom_angle_sensor
-                        .get_angle(&mut self.i2c, delay)
-                        .debugless_unwrap();
-                    a1 += 90.0;
-                    if a1 < 0.0 {
-                        a1 += 360.0
-                    };
-                    let mut a2 = self
-                        .top_angle_sensor
-                        .get_angle(&mut self.i2c, delay)
-                        .debugless_unwrap();
-                    a2 += 2.0;
-                    a2 = -a2;
-                    a2 -= 90.0;
-                    if a2 < 0.0 {
-                        a2 += 360.0
-                    };
-
-                    println!("{}", Response::Magnets(a1, a2));
-                }
-                Command::CalibrateArm => {
-                    self.calibrate_arm(delay);
-                }
-                Command::CalibrateSideways => {
-                    self.calibrate_sideways(delay);
-                }
-                Command::MoveSideways(angle) => {
-                    self.sideways_stepper.set_velocity(800.0);
-                    self.sideways_stepper
-                        .goto_angle(angle * SIDEWAYS_DEGREE_PER_M);
-                }
-                Command::MoveTopArm(angle) => {
-                    self.top_arm_stepper.set_velocity(150.0);
-                    self.top_arm_stepper.goto_angle(angle * TOP_RATIO);
-                }
-                Command::MoveBottomArm(angle) => {
-                    self.bottom_arm_stepper.set_velocity(600.0);
-                    self.bottom_arm_stepper.goto_angle(angle * BOT_RATIO);
+    fn respond(&self, response: Response) {
+        match self.codec {
+            Codec::Text => println!("{}", response),
+            Codec::Binary => {
+                let mut buf = [0; MAX_FRAME_LEN];
+                if let Ok(len) = response.to_frame(&mut buf) {
+                    serial_write(&buf[..len]);
                 }
-                Command::Queue(a1, a2, sd, speed_scale_factor) => {
-                    self.movement_buffer.push_back((
-                        a1 * BOT_RATIO,
-                        (a2 + a1 / TOP_RATIO) * TOP_RATIO,
-                        sd * SIDEWAYS_DEGREE_PER_M,
-                        speed_scale_factor,
-                    ));
-                }
-                Command::QueueSize => {
-                    println!(
-                        "{}",
-                        Response::QueueSize(self.movement_buffer.len() as u32, 300).to_string()
-                    );
+            }
+        }
+    }
+
+    /// Sends an event if the host has enabled them.
+    fn push_event(&self, event: Event) {
+        if self.events_enabled {
+            self.respond(Response::Event(event));
+        }
+    }
+
+    pub fn parse_line(&mut self, delay: &mut Delay, line: &str) {
+        if line.trim().is_empty() {
+            return;
+        }
+        let (seq, line) = text::split_seq(line);
+        self.handle_command(delay, Codec::Text, seq, Command::from_str(line).ok());
+    }
+
+    pub fn parse_frame(&mut self, delay: &mut Delay, frame: &[u8]) {
+        let seq = frame::frame_seq(frame);
+        self.handle_command(delay, Codec::Binary, seq, Command::from_frame(frame).ok());
+    }
+
+    /// Runs a command, acknowledging it if it has a sequence id. Failures are answered
+    /// with [`Response::Error`], before the ack so that the host knows which command
+    /// failed, except for the commands that are acknowledged first, see [`acks_first`].
+    fn handle_command(
+        &mut self,
+        delay: &mut Delay,
+        codec: Codec,
+        seq: Option<u16>,
+        command: Option<Command>,
+    ) {
+        self.codec = codec;
+        let mut ack = None;
+        let command = match (seq, command) {
+            (Some(seq), None) => return self.respond(Response::Nack(seq)),
+            (None, None) => return self.respond(Response::Error(ErrorCode::Parse, 0)),
+            // The ack was lost and the host sent the command again.
+            (Some(seq), Some(_)) if self.last_seq == Some(seq) => {
+                return self.respond(Response::Ack(seq));
+            }
+            (Some(seq), Some(command)) => {
+                self.last_seq = Some(seq);
+                if acks_first(&command) {
+                    self.respond(Response::Ack(seq));
+                } else {
+                    ack = Some(seq);
                 }
-                Command::Position => {
-                    println!(
-                        "{}",
-                        Response::Position(
-                            self.bottom_arm_stepper.get_angle() / BOT_RATIO,
-                            self.top_arm_stepper.get_angle() / TOP_RATIO
-                                - (self.bottom_arm_stepper.get_angle() / BOT_RATIO) / TOP_RATIO,
-                            self.sideways_stepper.get_angle() / SIDEWAYS_DEGREE_PER_M,
-                        )
-                        .to_string()
-                    );
+                command
+            }
+            (None, Some(command)) => command,
+        };
+        if let Err((code, detail)) = self.run_command(delay, command) {
+            self.respond(Response::Error(code, detail));
+        }
+        if let Some(seq) = ack {
+            self.respond(Response::Ack(seq));
+        }
+    }
+
+    /// Converts the angles of a segment to stepper angles and queues it.
+    fn queue_segment(&mut self, segment: Segment) {
+        let Segment {
+            bottom: a1,
+            top: a2,
+            sideways: sd,
+            speed,
+        } = segment;
+        let [bottom, top, sideways] = self.ratios().to_steppers(a1, a2, sd);
+        self.before_motion();
+        self.motion.queue((bottom, top, sideways, speed));
+    }
+
+    /// Fails unless the calibrations that a movement depends on have been done and the
+    /// drivers it needs are on.
+    fn check_calibrated(&self, sideways: bool, arm: bool) -> Result<(), (ErrorCode, u32)> {
+        if sideways {
+            self.check_enabled(Axis::Sideways)?;
+        }
+        if arm {
+            self.check_enabled(Axis::Bottom)?;
+            self.check_enabled(Axis::Top)?;
+        }
+        if (sideways && !self.is_sideways_calibrated) || (arm && !self.is_arm_calibrated) {
+            return Err((ErrorCode::NotCalibrated, 0));
+        }
+        Ok(())
+    }
+
+    /// Fails unless the arm stands still with nothing queued, since writing the flash
+    /// stops the steppers for as long as it takes.
+    fn check_idle(&self) -> Result<(), (ErrorCode, u32)> {
+        if self.motion.state() != MotionState::Idle || self.motion.queue_len() > 0 {
+            return Err((ErrorCode::Busy, 0));
+        }
+        Ok(())
+    }
+
+    fn check_enabled(&self, axis: Axis) -> Result<(), (ErrorCode, u32)> {
+        if !self.drivers_enabled[axis as usize] {
+            return Err((ErrorCode::Disabled, axis as u32));
+        }
+        Ok(())
+    }
+
+    /// Turns a driver on or off. Off stops the movements first, and the axis has to be
+    /// calibrated again since the motor can be turned by hand.
+    fn enable_driver(&mut self, axis: Axis, enabled: bool) {
+        if enabled == self.drivers_enabled[axis as usize] {
+            return;
+        }
+        if !enabled {
+            if self.motion.state() != MotionState::Idle || self.motion.queue_len() > 0 {
+                self.motion.request(Request::Stop);
+            }
+            match axis {
+                Axis::Sideways => self.is_sideways_calibrated = false,
+                _ => self.is_arm_calibrated = false,
+            }
+            // The saved position would be wrong after the motor has been turned.
+            self.before_motion();
+        }
+        self.motion.request(Request::Enable(axis, enabled));
+        self.drivers_enabled[axis as usize] = enabled;
+    }
+
+    /// Fails if an arm angle in degrees or a sideways position in meters is outside of
+    /// the travel limits of the axis.
+    fn check_travel(&self, axis: Axis, value: f32) -> Result<(), (ErrorCode, u32)> {
+        let (min, max) = match axis {
+            Axis::Bottom => (Param::BotArmMin, Param::BotArmMax),
+            Axis::Top => (Param::TopArmMin, Param::TopArmMax),
+            Axis::Sideways => (Param::SidewaysMin, Param::SidewaysMax),
+        };
+        if !(self.params.get(min)..=self.params.get(max)).contains(&value) {
+            return Err((ErrorCode::OutOfRange, axis as u32));
+        }
+        Ok(())
+    }
+
+    fn check_segment_travel(&self, segment: &Segment) -> Result<(), (ErrorCode, u32)> {
+        self.check_travel(Axis::Bottom, segment.bottom)?;
+        self.check_travel(Axis::Top, segment.top)?;
+        self.check_travel(Axis::Sideways, segment.sideways)
+    }
+
+    fn run_command(&mut self, delay: &mut Delay, command: Command) -> Result<(), (ErrorCode, u32)> {
+        match command {
+            Command::Magnets => {
+                let (a1, a2) = self.read_angles(delay)?;
+                self.respond(Response::Magnets(a1.angle, a2.angle));
+            }
+            Command::CalibrateArm => {
+                self.calibrate_arm(delay)?;
+            }
+            Command::CalibrateSideways => {
+                self.calibrate_sideways()?;
+            }
+            Command::MoveSideways(angle)
+            | Command::MoveTopArm(angle)
+            | Command::MoveBottomArm(angle)
+                if !angle.is_finite() =>
+            {
+                return Err((ErrorCode::Unreachable, 0));
+            }
+            Command::MoveSideways(angle) => {
+                self.check_calibrated(true, false)?;
+                self.check_travel(Axis::Sideways, angle)?;
+                self.before_motion();
+                self.move_axis(Axis::Sideways, angle);
+            }
+            Command::MoveTopArm(angle) => {
+                self.check_calibrated(false, true)?;
+                self.check_travel(Axis::Top, angle)?;
+                self.before_motion();
+                self.move_axis(Axis::Top, angle);
+            }
+            Command::MoveBottomArm(angle) => {
+                self.check_calibrated(false, true)?;
+                self.check_travel(Axis::Bottom, angle)?;
+                self.before_motion();
+                self.move_axis(Axis::Bottom, angle);
+            }
+            Command::Queue {
+                bottom,
+                top,
+                sideways,
+                speed,
+            } => {
+                let segment = Segment {
+                    bottom,
+                    top,
+                    sideways,
+                    speed,
+                };
+                if !segment.is_finite() {
+                    return Err((ErrorCode::Unreachable, 0));
                 }
-                Command::IsCalibrated => {
-                    println!(
-                        "{}",
-                        Response::IsCalibrated(self.is_sideways_calibrated).to_string()
-                    );
+                self.check_calibrated(true, true)?;
+                self.check_segment_travel(&segment)?;
+                if self.motion.queue_len() >= MAX_QUEUE_LEN {
+                    return Err((ErrorCode::QueueFull, MAX_QUEUE_LEN as u32));
                 }
-                Command::Grip => {
-                    self.servo_channel.set_duty(1000 + 370);
+                self.queue_segment(segment);
+            }
+            Command::QueueMany(segments) => {
+                if !segments.iter().all(Segment::is_finite) {
+                    return Err((ErrorCode::Unreachable, 0));
                 }
-                Command::Release => {
-                    self.servo_channel.set_duty(1000);
+                self.check_calibrated(true, true)?;
+                for segment in &segments {
+                    self.check_segment_travel(segment)?;
                 }
-                Command::RestartToBoot => {
-                    reset_to_usb_boot(0, 0);
+                let room = MAX_QUEUE_LEN.saturating_sub(self.motion.queue_len());
+                let accepted = segments.len().min(room);
+                for &segment in &segments[..accepted] {
+                    self.queue_segment(segment);
                 }
-                Command::ChessButton => {
-                    println!(
-                        "{}",
-                        Response::ChessButtonStatus(self.chess_button_been_pressed)
-                    );
-                    self.chess_button_been_pressed = false;
+                self.respond(Response::Queued(
+                    accepted as u32,
+                    self.motion.queue_len() as u32,
+                ));
+            }
+            Command::QueueSize => {
+                self.respond(Response::QueueSize(
+                    self.motion.queue_len() as u32,
+                    MAX_QUEUE_LEN as u32,
+                ));
+            }
+            Command::Position => {
+                let (bottom, top, sideways) = self.ratios().from_steppers(self.motion.angles());
+                self.respond(Response::Position(bottom, top, sideways));
+            }
+            Command::IsCalibrated => {
+                self.respond(Response::IsCalibrated(self.is_sideways_calibrated));
+            }
+            Command::Grip => {
+                self.servo_channel
+                    .set_duty(self.params.get(Param::GripDuty) as u16);
+            }
+            Command::Release => {
+                self.servo_channel
+                    .set_duty(self.params.get(Param::ReleaseDuty) as u16);
+            }
+            Command::RestartToBoot => {
+                reset_to_usb_boot(0, 0);
+            }
+            Command::ChessButton => {
+                self.respond(Response::ChessButtonStatus(self.chess_button_been_pressed));
+                self.chess_button_been_pressed = false;
+            }
+            Command::Events(enabled) => {
+                self.events_enabled = enabled;
+            }
+            Command::Stop => {
+                self.motion.request(Request::Stop);
+            }
+            Command::Pause => {
+                self.motion.request(Request::Pause);
+            }
+            Command::Resume => {
+                self.motion.request(Request::Resume);
+            }
+            Command::ClearQueue => {
+                self.motion.request(Request::ClearQueue);
+            }
+            Command::MotionStatus => {
+                self.respond(Response::MotionStatus(
+                    self.motion.state(),
+                    self.motion.queue_len() as u32,
+                ));
+            }
+            Command::SetParam(param, value) => {
+                self.check_idle()?;
+                let params = &mut self.params;
+                if !self.motion.parked(|| params.set(param, value)) {
+                    return Err((ErrorCode::BadValue, param as u32));
                 }
+                self.motion
+                    .request(Request::SetLimits(limits(&self.params)));
+                hardware::set_log_level(log_level(&self.params));
+            }
+            Command::GetParam(param) => {
+                self.respond(Response::ParamValue(param, self.params.get(param)));
+            }
+            Command::ResetParams => {
+                self.check_idle()?;
+                let params = &mut self.params;
+                self.motion.parked(|| params.reset());
+                self.motion
+                    .request(Request::SetLimits(limits(&self.params)));
+                hardware::set_log_level(log_level(&self.params));
             }
+            Command::Protocol => {
+                self.respond(Response::Protocol(
+                    Command::SCHEMA.hash(),
+                    Response::SCHEMA.hash(),
+                ));
+            }
+            Command::Credit => {
+                // Counted from the same point as the credit sent from now on.
+                let dequeued = self.motion.dequeued();
+                self.credited = dequeued;
+                let queued = self.motion.queued().wrapping_sub(dequeued) as usize;
+                self.respond(Response::Credit(MAX_QUEUE_LEN.saturating_sub(queued) as u32));
+            }
+            Command::SetStepSize(axis, size) => {
+                let step_size = match size {
+                    1 => StepSize::DIV1,
+                    2 => StepSize::DIV2,
+                    4 => StepSize::DIV4,
+                    8 => StepSize::DIV8,
+                    16 => StepSize::DIV16,
+                    _ => return Err((ErrorCode::StepSize, size)),
+                };
+                self.motion.request(Request::SetStepSize(axis, step_size));
+            }
+            Command::EnableDriver(axis, enabled) => {
+                self.enable_driver(axis, enabled);
+            }
+            Command::CalibrateSensors {
+                bottom_from,
+                bottom_to,
+                top_from,
+                top_to,
+            } => {
+                self.calibrate_sensors(delay, (bottom_from, bottom_to), (top_from, top_to))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves one axis to an arm angle in degrees or a sideways position in meters,
+    /// regardless of the queue.
+    fn move_axis(&mut self, axis: Axis, angle: f32) {
+        let (ratio, velocity) = match axis {
+            Axis::Bottom => (Param::BotRatio, 600.0),
+            Axis::Top => (Param::TopRatio, 150.0),
+            Axis::Sideways => (Param::SidewaysDegreePerM, 800.0),
+        };
+        self.motion.request(Request::Move {
+            axis,
+            angle: angle * self.params.get(ratio),
+            velocity,
+        });
+    }
+
+    fn ratios(&self) -> Ratios {
+        ratios(&self.params)
+    }
+
+    /// Compares the angle sensors with the stepper positions every
+    /// [`DRIFT_CHECK_INTERVAL_US`] while moving, and once after stopping, and reports or
+    /// corrects lost steps.
+    fn check_drift(&mut self, delay: &mut Delay) {
+        let now = motion::now_us();
+        let state = self.motion.state();
+        let at_rest = state != MotionState::Moving;
+        if !self.is_arm_calibrated
+            || now.wrapping_sub(self.last_drift_check) < DRIFT_CHECK_INTERVAL_US
+            || (at_rest && self.drift_checked_at_rest)
+        {
+            return;
+        }
+        self.last_drift_check = now;
+        self.drift_checked_at_rest = at_rest;
+
+        // The steppers keep moving while the sensors are read, so compare with the middle.
+        let before = self.motion.angles();
+        let Ok((bottom, top)) = self.read_angles(delay) else {
+            return;
+        };
+        // Lost steps can't be told apart from noise that large.
+        if bottom.uncertainty.max(top.uncertainty) > self.params.get(Param::DriftLimit) / 2.0 {
+            return;
+        }
+        let after = self.motion.angles();
+        let counted = self
+            .ratios()
+            .from_steppers(core::array::from_fn(|i| (before[i] + after[i]) / 2.0));
+
+        let Some(drift) = self.drift.check(
+            (bottom.angle, top.angle),
+            (counted.0, counted.1),
+            self.params.get(Param::DriftLimit),
+            self.params.get(Param::DriftCorrection),
+        ) else {
+            return;
+        };
+        log!(
+            Level::Info,
+            "off by {} degrees at the bottom and {} at the top",
+            drift.bottom,
+            drift.top
+        );
+        if drift.correct {
+            // The saved position is off now too, it is saved again once the arm has stood
+            // still for long enough.
+            self.before_motion();
+            let [bottom, top, _] = self.ratios().to_steppers(drift.bottom, drift.top, 0.0);
+            self.motion.request(Request::CorrectArm { bottom, top });
+        }
+        self.push_event(match state {
+            MotionState::Moving => Event::Stall(drift.bottom, drift.top, drift.correct),
+            _ => Event::Drift(drift.bottom, drift.top, drift.correct),
+        });
+    }
+
+    /// Invalidates the saved position, which has to be done before the steppers move.
+    fn before_motion(&mut self) {
+        self.last_moving = motion::now_us();
+        if self.position_saved {
+            self.motion.parked(position::invalidate);
+            self.position_saved = false;
+        }
+    }
+
+    /// Saves the position once the arm has stood still for [`POSITION_SAVE_DELAY_US`],
+    /// so that the sideways calibration can be skipped after a power cycle.
+    fn save_position(&mut self) {
+        let now = motion::now_us();
+        if self.motion.state() != MotionState::Idle || self.motion.queue_len() > 0 {
+            self.last_moving = now;
+            return;
         }
+        if self.position_saved
+            || !self.is_sideways_calibrated
+            || now.wrapping_sub(self.last_moving) < POSITION_SAVE_DELAY_US
+        {
+            return;
+        }
+        let angles = self.motion.angles();
+        self.motion.parked(|| position::save(angles));
+        self.position_saved = true;
     }
 
-    fn is_in_position_margin(&mut self, margin: i64) -> bool {
-        self.top_arm_stepper.is_at_target_margin(margin)
-            && self.bottom_arm_stepper.is_at_target_margin(margin)
-            && self.sideways_stepper.is_at_target_margin(margin)
-    }
-
-    fn check_queue(&mut self) {
-        if !self.movement_buffer.is_empty() && self.is_in_position_margin(3) {
-            let (a1, a2, sd, speed_scale_factor) = self.movement_buffer.pop_front().unwrap();
-            let speed_scale_factor = (1.0_f32).min(speed_scale_factor);
-            let max_time = ((libm::fabsf(self.bottom_arm_stepper.get_angle() - a1)
-                / BOT_ARM_MAX_SPEED)
-                .max(libm::fabsf(self.top_arm_stepper.get_angle() - a2) / TOP_ARM_MAX_SPEED)
-                .max(libm::fabsf(self.sideways_stepper.get_angle() - sd) / SIDEWAYS_MAX_SPEED)
-                + 0.0001)
-                / speed_scale_factor;
-
-            // let norma1 = libm::fabsf(self.bottom_arm_stepper.get_angle() - a1)/max_time;
-            // let norma2 = libm::fabsf(self.top_arm_stepper.get_angle() - a2)/max_time;
-            // let normsd = libm::fabsf(self.sideways_stepper.get_angle() - sd)/max_time;
-
-            self.bottom_arm_stepper
-                .set_velocity((self.bottom_arm_stepper.get_angle() - a1) / max_time);
-            self.top_arm_stepper
-                .set_velocity((self.top_arm_stepper.get_angle() - a2) / max_time);
-            self.sideways_stepper
-                .set_velocity((self.sideways_stepper.get_angle() - sd) / max_time);
-
-            self.bottom_arm_stepper.goto_angle(a1);
-            self.top_arm_stepper.goto_angle(a2);
-            self.sideways_stepper.goto_angle(sd);
+    /// Sends the room freed up in the queue since the last [`Event::Credit`], a few
+    /// movements at a time.
+    fn send_credit(&mut self) {
+        let dequeued = self.motion.dequeued();
+        let freed = dequeued.wrapping_sub(self.credited);
+        if freed >= CREDIT_BATCH || (freed > 0 && self.motion.queue_len() == 0) {
+            self.credited = dequeued;
+            self.push_event(Event::Credit(freed));
         }
     }
 
-    pub fn run(&mut self, timer: &Timer) {
+    pub fn run(&mut self, delay: &mut Delay) {
+        self.check_drift(delay);
+        self.save_position();
+        self.send_credit();
+
         let pressed = self.chess_button.is_low().unwrap();
         if !self.chess_button_last_state && pressed {
             self.chess_button_been_pressed = true;
+            self.push_event(Event::ChessButton);
         }
         self.chess_button_last_state = pressed;
 
-        self.check_queue();
-        self.sideways_stepper.run(timer);
-        self.bottom_arm_stepper.run(timer);
-        self.top_arm_stepper.run(timer);
+        while let Some(event) = self.motion.next_event() {
+            self.push_event(event);
+        }
+    }
+}
+
+fn ratios(params: &Params) -> Ratios {
+    Ratios {
+        bottom: params.get(Param::BotRatio),
+        top: params.get(Param::TopRatio),
+        sideways: params.get(Param::SidewaysDegreePerM),
+    }
+}
+
+/// The max speeds, accelerations and travel the steppers are limited to.
+fn limits(params: &Params) -> Limits {
+    Limits {
+        max_speeds: [
+            params.get(Param::BotArmMaxSpeed),
+            params.get(Param::TopArmMaxSpeed),
+            params.get(Param::SidewaysMaxSpeed),
+        ],
+        max_accels: [
+            params.get(Param::BotArmMaxAccel),
+            params.get(Param::TopArmMaxAccel),
+            params.get(Param::SidewaysMaxAccel),
+        ],
+        profile: if params.get(Param::MotionProfile) == 0.0 {
+            Profile::Trapezoidal
+        } else {
+            Profile::SCurve
+        },
+        // Only a backstop, the targets are checked against the arm angles before they
+        // are queued, see `Arm::check_travel`.
+        travel: ratios(params).travel(
+            (params.get(Param::BotArmMin), params.get(Param::BotArmMax)),
+            (params.get(Param::TopArmMin), params.get(Param::TopArmMax)),
+            (
+                params.get(Param::SidewaysMin),
+                params.get(Param::SidewaysMax),
+            ),
+        ),
+    }
+}
+
+/// The commands that may take longer than the host waits for an ack, which are
+/// acknowledged before they are run. The host waits for their answer or event anyway,
+/// and an error comes instead of it.
+fn acks_first(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Magnets
+            | Command::CalibrateArm
+            | Command::CalibrateSideways
+            | Command::CalibrateSensors { .. }
+            | Command::RestartToBoot
+    )
+}
+
+fn log_level(params: &Params) -> Level {
+    match params.get(Param::LogLevel) as u8 {
+        0 => Level::Error,
+        1 => Level::Warn,
+        2 => Level::Info,
+        _ => Level::Debug,
     }
 }
 
@@ -320,7 +813,7 @@ fn start() -> ! {
 
     let timer = Timer::new(pac.TIMER, &mut pac.RESETS);
 
-    let sio = Sio::new(pac.SIO);
+    let mut sio = Sio::new(pac.SIO);
 
     let pins = Pins::new(
         pac.IO_BANK0,
@@ -366,6 +859,7 @@ fn start() -> ! {
             DynPin::from(pins.gpio14.into_push_pull_output()),
             DynPin::from(pins.gpio13.into_push_pull_output()),
         )),
+        Some(DynPin::from(pins.gpio18.into_push_pull_output())),
     );
 
     let mut bottom_arm_stepper = Stepper::new(
@@ -378,6 +872,7 @@ fn start() -> ! {
             DynPin::from(pins.gpio9.into_push_pull_output()),
             DynPin::from(pins.gpio8.into_push_pull_output()),
         )),
+        Some(DynPin::from(pins.gpio17.into_push_pull_output())),
     );
 
     let mut sideways_stepper = Stepper::new(
@@ -390,54 +885,113 @@ fn start() -> ! {
             DynPin::from(pins.gpio4.into_push_pull_output()),
             DynPin::from(pins.gpio3.into_push_pull_output()),
         )),
+        Some(DynPin::from(pins.gpio20.into_push_pull_output())),
     );
 
     bottom_arm_stepper.set_velocity(360.0);
     top_arm_stepper.set_velocity(50.0);
     sideways_stepper.set_velocity(180.0);
 
-    let mut line_buffer = String::with_capacity(4096);
+    let mut line_buffer = Vec::with_capacity(4096);
+    // Set between the zero bytes around a binary frame.
+    let mut in_frame = false;
+    // Set when the line buffer overflowed, the rest of the line or frame is dropped.
+    let mut overflowed = false;
+
+    let params = Params::load();
+    hardware::set_log_level(log_level(&params));
+    let mut multicore = Multicore::new(&mut pac.PSM, &mut pac.PPB, &mut sio.fifo);
+    let mut motion = motion::spawn(
+        &mut multicore.cores()[1],
+        [bottom_arm_stepper, top_arm_stepper, sideways_stepper],
+        DynPin::from(pins.gpio16.into_pull_up_input()),
+        timer,
+        limits(&params),
+    );
+    // Skips the sideways calibration if the arm was powered off standing still.
+    let restored = position::load();
+    if let Some(angles) = restored {
+        motion.request(Request::Restore(angles));
+    }
 
-    let bottom_angle_sensor = AngleSensor::new(&mut i2c, 0x18).debugless_unwrap();
+    let mut bottom_angle_sensor = AngleSensor::new(&mut i2c, 0x18).debugless_unwrap();
     // bottom_angle_sensor.mlx.set_gain(&mut I2CInterface {i2c: &mut i2c, address: 0x18}, Gain::X1).debugless_unwrap();
-    let top_angle_sensor = AngleSensor::new(&mut i2c, 0x19).debugless_unwrap();
+    let mut top_angle_sensor = AngleSensor::new(&mut i2c, 0x19).debugless_unwrap();
+    if let Some([bottom, top]) = calibration::load() {
+        bottom_angle_sensor.calibration = bottom;
+        top_angle_sensor.calibration = top;
+    }
 
     let mut arm = Arm {
         i2c,
-        top_arm_stepper,
-        sideways_stepper,
-        bottom_arm_stepper,
+        motion,
 
         bottom_angle_sensor,
         top_angle_sensor,
+        drift: DriftMonitor::default(),
+        last_drift_check: 0,
+        drift_checked_at_rest: false,
 
         chess_button: DynPin::from(pins.gpio22.into_pull_up_input()),
         chess_button_been_pressed: false,
         chess_button_last_state: false,
-        sideways_button: DynPin::from(pins.gpio16.into_pull_up_input()),
 
-        is_sideways_calibrated: false,
+        is_sideways_calibrated: restored.is_some(),
+        is_arm_calibrated: false,
         servo_channel: channel,
-        movement_buffer: VecDeque::new(),
+        codec: Codec::Text,
+        last_seq: None,
+        events_enabled: false,
+        params,
+        position_saved: restored.is_some(),
+        last_moving: 0,
+        credited: 0,
+        drivers_enabled: [true; 3],
     };
 
-    println!("{:+?}", arm.calibrate_arm(&mut delay));
+    if let Err((code, detail)) = arm.calibrate_arm(&mut delay) {
+        log!(
+            Level::Warn,
+            "the arm could not be calibrated: {code:?} {detail}"
+        );
+    }
 
     loop {
-        arm.run(&timer);
+        arm.run(&mut delay);
 
         while serial_available() {
             // So it dosn't wait too long between runs.
-            arm.run(&timer);
-
-            let ch = char::from(read_byte());
-            line_buffer.push(ch);
-            if line_buffer.len() >= 4096 {
-                line_buffer.clear();
-            }
-            if ch == '\n' {
-                arm.parse_command(&mut delay, &line_buffer);
-                line_buffer.clear();
+            arm.run(&mut delay);
+
+            match read_byte() {
+                0 => {
+                    if in_frame && !line_buffer.is_empty() {
+                        if !overflowed {
+                            arm.parse_frame(&mut delay, &line_buffer);
+                        }
+                        in_frame = false;
+                    } else {
+                        in_frame = true;
+                    }
+                    overflowed = false;
+                    line_buffer.clear();
+                }
+                b'\n' if !in_frame => {
+                    if !overflowed {
+                        if let Ok(line) = core::str::from_utf8(&line_buffer) {
+                            arm.parse_line(&mut delay, line);
+                        }
+                    }
+                    overflowed = false;
+                    line_buffer.clear();
+                }
+                byte => {
+                    line_buffer.push(byte);
+                    if line_buffer.len() >= 4096 {
+                        overflowed = true;
+                        line_buffer.clear();
+                    }
+                }
             }
             // Read and write serial.
         }
@@ -0,0 +1,239 @@
+//! Step generation, which runs on the second core so that reading the angle sensors or
+//! the serial on the first core never delays a step. The first core hands over
+//! movements and requests through [`Channel`]s and reads the positions back from
+//! atomics, neither core ever waits for a lock. The motion itself is in `arm_core`.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+
+use arm_core::{
+    channel::{Channel, Receiver, Sender},
+    motion::{Limits, Motion, Movement, Request, EVENT_CAPACITY, QUEUE_CAPACITY},
+    stepper::{HomingError, Stepper},
+    Clock,
+};
+use robby_fischer::{Event, MotionState};
+use rp_pico::{
+    hal::{
+        gpio::DynPin,
+        multicore::{Core, Stack},
+        Timer,
+    },
+    pac,
+};
+
+const REQUEST_CAPACITY: usize = 8;
+
+static MOVEMENTS: Channel<Movement, QUEUE_CAPACITY> = Channel::new();
+static REQUESTS: Channel<Request, REQUEST_CAPACITY> = Channel::new();
+static EVENTS: Channel<Event, EVENT_CAPACITY> = Channel::new();
+
+/// The bottom, top and sideways stepper angles as `f32` bits, see [`MotionHandle::angles`].
+static ANGLES: [AtomicU32; 3] = [AtomicU32::new(0), AtomicU32::new(0), AtomicU32::new(0)];
+/// See [`Motion::dequeued`].
+static DEQUEUED: AtomicU32 = AtomicU32::new(0);
+/// A [`MotionState`] as `u8`.
+static STATE: AtomicU8 = AtomicU8::new(MotionState::Idle as u8);
+/// The number of requests the second core has handled, wrapping.
+static HANDLED: AtomicU32 = AtomicU32::new(0);
+/// The [`HomingError`] of the last handled request as `u8`, 0 if it succeeded.
+static FAILED: AtomicU8 = AtomicU8::new(0);
+/// Set by the first core while it writes the flash, see [`MotionHandle::parked`].
+static PARK: AtomicBool = AtomicBool::new(false);
+/// Set by the second core while it waits in RAM.
+static PARKED: AtomicBool = AtomicBool::new(false);
+
+static mut CORE1_STACK: Stack<4096> = Stack::new();
+
+/// The RP2040 timer, which counts microseconds.
+pub struct TimerClock(pub Timer);
+
+impl Clock for TimerClock {
+    fn now_us(&self) -> u32 {
+        self.0.get_counter().ticks() as u32
+    }
+}
+
+/// The same counter as [`TimerClock`], for the first core that doesn't own the timer.
+pub fn now_us() -> u32 {
+    // Safety: reading the raw counter has no side effects.
+    unsafe { (*pac::TIMER::ptr()).timerawl.read().bits() }
+}
+
+type ArmMotion = Motion<DynPin, DynPin, TimerClock>;
+
+/// Starts step generation on the second core. The returned handle is how the first
+/// core talks to it.
+pub fn spawn(
+    core: &mut Core,
+    steppers: [Stepper<DynPin>; 3],
+    sideways_button: DynPin,
+    timer: Timer,
+    limits: Limits,
+) -> MotionHandle {
+    // Safety: `spawn` is only called once.
+    let (movements, movement_receiver) = unsafe { MOVEMENTS.split() };
+    let (requests, request_receiver) = unsafe { REQUESTS.split() };
+    let (event_sender, events) = unsafe { EVENTS.split() };
+
+    let motion = Motion::new(
+        steppers,
+        sideways_button,
+        TimerClock(timer),
+        limits,
+        movement_receiver,
+        event_sender,
+    );
+    publish(&motion);
+    // Safety: the stack is only used by the second core.
+    let stack = unsafe { &mut CORE1_STACK.mem };
+    core.spawn(stack, move || run(motion, request_receiver))
+        .unwrap();
+
+    MotionHandle {
+        movements,
+        requests,
+        events,
+        sent: 0,
+        queued: 0,
+    }
+}
+
+/// The loop of the second core.
+fn run(mut motion: ArmMotion, mut requests: Receiver<Request, REQUEST_CAPACITY>) -> ! {
+    let mut handled = 0_u32;
+    loop {
+        while let Some(request) = requests.recv() {
+            let failed = motion.handle(request).err().map_or(0, |error| error as u8);
+            FAILED.store(failed, Ordering::Relaxed);
+            // So that the first core sees the state the request left behind as soon as
+            // it returns from `MotionHandle::request`.
+            publish(&motion);
+            handled = handled.wrapping_add(1);
+            HANDLED.store(handled, Ordering::Release);
+        }
+        if PARK.load(Ordering::Acquire) {
+            park();
+        }
+        motion.run();
+        publish(&motion);
+    }
+}
+
+/// Makes the positions and the state visible to the first core.
+fn publish(motion: &ArmMotion) {
+    for (atomic, angle) in ANGLES.iter().zip(motion.angles()) {
+        atomic.store(angle.to_bits(), Ordering::Relaxed);
+    }
+    DEQUEUED.store(motion.dequeued(), Ordering::Relaxed);
+    STATE.store(motion.state() as u8, Ordering::Release);
+}
+
+/// Waits in RAM while the first core writes the flash, see [`MotionHandle::parked`].
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+fn park() {
+    PARKED.store(true, Ordering::Release);
+    while PARK.load(Ordering::Acquire) {}
+    PARKED.store(false, Ordering::Release);
+}
+
+/// The first core's end of the motion channels, see [`spawn`].
+pub struct MotionHandle {
+    movements: Sender<Movement, QUEUE_CAPACITY>,
+    requests: Sender<Request, REQUEST_CAPACITY>,
+    events: Receiver<Event, EVENT_CAPACITY>,
+    /// The number of requests sent, wrapping.
+    sent: u32,
+    /// The number of movements queued, wrapping.
+    queued: u32,
+}
+
+impl MotionHandle {
+    /// Sends a request and waits until the second core has handled it, which takes one
+    /// step at most unless it is a calibration. Requests overtake the queued movements
+    /// that have not started. The state and angles afterwards include the request.
+    pub fn request(&mut self, mut request: Request) {
+        while let Err(rejected) = self.requests.send(request) {
+            request = rejected;
+        }
+        self.sent = self.sent.wrapping_add(1);
+        while HANDLED.load(Ordering::Acquire) != self.sent {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Moves the sideways stepper to the limit switch, see [`Request::CalibrateSideways`].
+    pub fn calibrate_sideways(
+        &mut self,
+        max_travel: f32,
+        timeout_us: u32,
+    ) -> Result<(), HomingError> {
+        self.request(Request::CalibrateSideways {
+            max_travel,
+            timeout_us,
+        });
+        match FAILED.load(Ordering::Relaxed) {
+            0 => Ok(()),
+            failed if failed == HomingError::Timeout as u8 => Err(HomingError::Timeout),
+            _ => Err(HomingError::MaxTravel),
+        }
+    }
+
+    /// Queues a movement, returns false if the queue is full.
+    pub fn queue(&mut self, movement: Movement) -> bool {
+        let sent = self.movements.send(movement).is_ok();
+        if sent {
+            self.queued = self.queued.wrapping_add(1);
+        }
+        sent
+    }
+
+    /// The number of movements queued, wrapping.
+    pub fn queued(&self) -> u32 {
+        self.queued
+    }
+
+    /// The number of queued movements that have been started or cleared, wrapping. It
+    /// lags behind a little, but never gets ahead.
+    pub fn dequeued(&self) -> u32 {
+        DEQUEUED.load(Ordering::Relaxed)
+    }
+
+    /// The number of queued movements that have not started.
+    pub fn queue_len(&self) -> usize {
+        self.movements.len()
+    }
+
+    /// The bottom, top and sideways stepper angles in degrees.
+    pub fn angles(&self) -> [f32; 3] {
+        core::array::from_fn(|i| f32::from_bits(ANGLES[i].load(Ordering::Relaxed)))
+    }
+
+    pub fn state(&self) -> MotionState {
+        match STATE.load(Ordering::Acquire) {
+            state if state == MotionState::Paused as u8 => MotionState::Paused,
+            state if state == MotionState::Moving as u8 => MotionState::Moving,
+            _ => MotionState::Idle,
+        }
+    }
+
+    /// An event from the second core, like [`Event::SegmentStarted`].
+    pub fn next_event(&mut self) -> Option<Event> {
+        self.events.recv()
+    }
+
+    /// Runs `f` while the second core waits in RAM, which it has to while the flash is
+    /// written. The steppers stand still meanwhile.
+    pub fn parked<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        PARK.store(true, Ordering::Release);
+        while !PARKED.load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+        let result = f();
+        PARK.store(false, Ordering::Release);
+        while PARKED.load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+        result
+    }
+}
@@ -0,0 +1,175 @@
+//! The motion parameters, see [`Param`]. They are kept in the last sector of the flash,
+//! which `memory.x` leaves out of the program.
+
+use alloc::vec;
+use burktelefon::frame::{crc16, Binary, Field, Reader, Writer};
+use robby_fischer::Param;
+
+/// The number of parameters.
+const COUNT: usize = Param::SCHEMA.variants.len();
+
+/// Where the flash is mapped.
+pub const XIP_BASE: u32 = 0x1000_0000;
+pub const FLASH_SIZE: u32 = 2 * 1024 * 1024;
+pub const SECTOR_SIZE: u32 = 4096;
+/// The offset of the reserved sector from the start of the flash.
+const PARAMS_OFFSET: u32 = FLASH_SIZE - SECTOR_SIZE;
+
+/// Marks a sector that has been written by [`Params::save`], "RFP1".
+const MAGIC: u32 = 0x3150_4652;
+
+/// The parameter with this tag.
+fn param(index: usize) -> Param {
+    Param::decode(&mut Reader::new(&[index as u8])).unwrap()
+}
+
+/// The value a parameter has before it is changed or after [`Params::reset`].
+fn default(param: Param) -> f32 {
+    match param {
+        Param::BotArmMaxSpeed => 1200.0,
+        Param::TopArmMaxSpeed => 120.0,
+        Param::SidewaysMaxSpeed => 1600.0,
+        // 66.0 / 21.0 is the gear ratio, but this works better.
+        Param::TopRatio => 66.0 / 20.0,
+        Param::BotRatio => (34.0 / 8.0) * (54.0 / 10.0),
+        Param::SidewaysDegreePerM => 360.0 / (18.0 * 0.002),
+        Param::GripDuty => 1370.0,
+        Param::ReleaseDuty => 1000.0,
+        // Constant error on the bottom sensor, might be because the sensor is not
+        // aligned perfectly with the magnet.
+        Param::BottomAngleOffset => 90.0,
+        Param::TopAngleOffset => 2.0,
+        // Full speed in a quarter of a second.
+        Param::BotArmMaxAccel => 4800.0,
+        Param::TopArmMaxAccel => 480.0,
+        Param::SidewaysMaxAccel => 6400.0,
+        Param::MotionProfile => 1.0,
+        Param::DriftLimit => 2.0,
+        // Lost steps are only reported unless this is changed.
+        Param::DriftCorrection => 0.0,
+        Param::BotArmMin => -20.0,
+        Param::BotArmMax => 200.0,
+        Param::TopArmMin => -10.0,
+        Param::TopArmMax => 190.0,
+        Param::SidewaysMin => -0.01,
+        Param::SidewaysMax => 0.6,
+        Param::HomingTravel => 0.7,
+        Param::HomingTimeout => 20.0,
+        Param::LogLevel => 2.0,
+    }
+}
+
+/// True if the parameter can have the value.
+fn is_valid(param: Param, value: f32) -> bool {
+    match param {
+        Param::BottomAngleOffset
+        | Param::TopAngleOffset
+        | Param::BotArmMin
+        | Param::BotArmMax
+        | Param::TopArmMin
+        | Param::TopArmMax
+        | Param::SidewaysMin
+        | Param::SidewaysMax => value.is_finite(),
+        Param::GripDuty | Param::ReleaseDuty => (0.0..=u16::MAX as f32).contains(&value),
+        Param::MotionProfile => value == 0.0 || value == 1.0,
+        Param::LogLevel => [0.0, 1.0, 2.0, 3.0].contains(&value),
+        Param::DriftCorrection => value.is_finite() && value >= 0.0,
+        _ => value.is_finite() && value > 0.0,
+    }
+}
+
+pub struct Params {
+    /// Indexed by the tag of the [`Param`].
+    values: [f32; COUNT],
+}
+
+impl Params {
+    /// The values the firmware was built with.
+    pub fn defaults() -> Params {
+        let mut values = [0.0; COUNT];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = default(param(i));
+        }
+        Params { values }
+    }
+
+    /// Reads the saved values, or returns the defaults if there are none. Parameters
+    /// added after the values were saved get their defaults.
+    pub fn load() -> Params {
+        // Safety: the flash is always mapped and only written by `save`.
+        let sector = unsafe {
+            core::slice::from_raw_parts(
+                (XIP_BASE + PARAMS_OFFSET) as *const u8,
+                SECTOR_SIZE as usize,
+            )
+        };
+        let mut params = Params::defaults();
+        let _ = params.decode(sector);
+        params
+    }
+
+    /// Writes the values to flash.
+    pub fn save(&self) {
+        let mut sector = vec![0xff; SECTOR_SIZE as usize];
+        self.encode(&mut sector);
+        // Nothing may run from flash while it is written, which includes the USB
+        // interrupt.
+        cortex_m::interrupt::free(|_| unsafe {
+            rp2040_flash::flash::flash_range_erase_and_program(PARAMS_OFFSET, &sector, true);
+        });
+    }
+
+    /// Restores the defaults and saves them.
+    pub fn reset(&mut self) {
+        *self = Params::defaults();
+        self.save();
+    }
+
+    pub fn get(&self, param: Param) -> f32 {
+        self.values[param as usize]
+    }
+
+    /// Changes a parameter and saves all of them. Returns false if the value is not
+    /// allowed.
+    pub fn set(&mut self, param: Param, value: f32) -> bool {
+        if !is_valid(param, value) {
+            return false;
+        }
+        self.values[param as usize] = value;
+        self.save();
+        true
+    }
+
+    /// The magic number, the number of values, the values and a CRC of everything
+    /// before it.
+    fn encode(&self, buf: &mut [u8]) {
+        let mut w = Writer::new(buf);
+        let _ = MAGIC.write(&mut w);
+        let _ = (COUNT as u32).write(&mut w);
+        for value in &self.values {
+            let _ = value.write(&mut w);
+        }
+        let crc = crc16(w.written());
+        let _ = crc.write(&mut w);
+    }
+
+    fn decode(&mut self, sector: &[u8]) -> Option<()> {
+        let mut r = Reader::new(sector);
+        if u32::read(&mut r).ok()? != MAGIC {
+            return None;
+        }
+        let count = u32::read(&mut r).ok()? as usize;
+        let len = 8 + count.checked_mul(4)?;
+        let crc = u16::from_le_bytes(sector.get(len..len + 2)?.try_into().ok()?);
+        if crc16(&sector[..len]) != crc {
+            return None;
+        }
+        for i in 0..count {
+            let value = f32::read(&mut r).ok()?;
+            if i < COUNT && is_valid(param(i), value) {
+                self.values[i] = value;
+            }
+        }
+        Some(())
+    }
+}
@@ -0,0 +1,113 @@
+//! The stepper angles, saved to flash while the arm stands still so that the sideways
+//! calibration can be skipped after a power cycle. The sector before the parameters is
+//! used as a log of 256 byte pages, one record per save, so that it is only erased once
+//! every 16 saves.
+//!
+//! A record is valid until its flag is programmed to zero, which is done before the
+//! steppers move again and needs no erase. A power cycle while moving, or before the
+//! position was saved, leaves no valid record.
+
+use burktelefon::frame::{crc16, Field, Reader, Writer};
+
+use crate::params::{FLASH_SIZE, SECTOR_SIZE, XIP_BASE};
+
+/// The offset of the sector from the start of the flash.
+const POSITION_OFFSET: u32 = FLASH_SIZE - 2 * SECTOR_SIZE;
+/// The smallest size that can be programmed.
+const PAGE_SIZE: usize = 256;
+const PAGES: usize = SECTOR_SIZE as usize / PAGE_SIZE;
+
+/// Marks a written page, "RFS1".
+const MAGIC: u32 = 0x3153_4652;
+/// The magic number, three angles and a CRC.
+const RECORD_LEN: usize = 4 + 3 * 4 + 2;
+/// Where the flag is in the page, it is all ones while the record is valid.
+const FLAG_OFFSET: usize = PAGE_SIZE - 4;
+
+fn sector() -> &'static [u8] {
+    // Safety: the flash is always mapped and only written by this module.
+    unsafe {
+        core::slice::from_raw_parts(
+            (XIP_BASE + POSITION_OFFSET) as *const u8,
+            SECTOR_SIZE as usize,
+        )
+    }
+}
+
+fn page(index: usize) -> &'static [u8] {
+    &sector()[index * PAGE_SIZE..(index + 1) * PAGE_SIZE]
+}
+
+fn is_written(page: &[u8]) -> bool {
+    page[..4] != [0xff; 4]
+}
+
+/// The index of the last written page.
+fn last_page() -> Option<usize> {
+    (0..PAGES).take_while(|&i| is_written(page(i))).last()
+}
+
+/// The bottom, top and sideways stepper angles of the last save, unless the steppers
+/// may have moved since.
+pub fn load() -> Option<[f32; 3]> {
+    let page = page(last_page()?);
+    if page[FLAG_OFFSET..] != [0xff; 4] {
+        return None;
+    }
+    let crc = u16::from_le_bytes(page[RECORD_LEN - 2..RECORD_LEN].try_into().ok()?);
+    if crc16(&page[..RECORD_LEN - 2]) != crc {
+        return None;
+    }
+    let mut r = Reader::new(page);
+    if u32::read(&mut r).ok()? != MAGIC {
+        return None;
+    }
+    let mut angles = [0.0; 3];
+    for angle in &mut angles {
+        *angle = f32::read(&mut r).ok()?;
+    }
+    Some(angles)
+}
+
+/// Writes a valid record of the bottom, top and sideways stepper angles.
+pub fn save(angles: [f32; 3]) {
+    let mut buf = [0xff; PAGE_SIZE];
+    let mut w = Writer::new(&mut buf);
+    let _ = MAGIC.write(&mut w);
+    for angle in angles {
+        let _ = angle.write(&mut w);
+    }
+    let crc = crc16(w.written());
+    let _ = crc.write(&mut w);
+
+    let mut next = last_page().map_or(0, |i| i + 1);
+    // Nothing may run from flash while it is written, which includes the USB interrupt.
+    cortex_m::interrupt::free(|_| unsafe {
+        if next == PAGES {
+            rp2040_flash::flash::flash_range_erase(POSITION_OFFSET, SECTOR_SIZE, true);
+            next = 0;
+        }
+        rp2040_flash::flash::flash_range_program(
+            POSITION_OFFSET + (next * PAGE_SIZE) as u32,
+            &buf,
+            true,
+        );
+    });
+}
+
+/// Clears the flag of the last record, which has to be done before the steppers move.
+pub fn invalidate() {
+    let Some(last) = last_page() else {
+        return;
+    };
+    let mut buf = [0xff; PAGE_SIZE];
+    buf[FLAG_OFFSET..].fill(0);
+    // Programming only clears bits, the rest of the page stays as it is.
+    cortex_m::interrupt::free(|_| unsafe {
+        rp2040_flash::flash::flash_range_program(
+            POSITION_OFFSET + (last * PAGE_SIZE) as u32,
+            &buf,
+            true,
+        );
+    });
+}
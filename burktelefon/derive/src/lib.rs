@@ -0,0 +1,687 @@
+use proc_macro::TokenStream;
+use proc_macro2::{Literal, Span};
+use quote::{format_ident, quote, ToTokens};
+use syn::{
+    parse_macro_input, punctuated::Punctuated, Attribute, DeriveInput, Expr, ExprLit, Fields,
+    Ident, Lit, LitStr, Token, Type,
+};
+
+/// A variant together with the options given in its `#[burk(...)]` attributes.
+struct BurkVariant<'a> {
+    variant: &'a syn::Variant,
+    cmd_name: String,
+    /// Where the command name was given, or the variant name if it wasn't.
+    cmd_span: Span,
+    /// Other names that are accepted when parsing.
+    aliases: Vec<LitStr>,
+    /// The protocol version that added the variant.
+    since: u32,
+    /// The variant of the response enum that answers this command.
+    reply: Option<Ident>,
+    fields: Vec<BurkField<'a>>,
+}
+
+impl BurkVariant<'_> {
+    /// The command name followed by the aliases.
+    fn names(&self) -> impl Iterator<Item = (String, Span)> + '_ {
+        let aliases = self.aliases.iter().map(|lit| (lit.value(), lit.span()));
+        std::iter::once((self.cmd_name.clone(), self.cmd_span)).chain(aliases)
+    }
+}
+
+/// A field together with the options given in its `#[burk(...)]` attributes.
+struct BurkField<'a> {
+    field: &'a syn::Field,
+    kind: FieldKind,
+    /// Used when the field is missing at the end of the line.
+    default: Option<Expr>,
+}
+
+/// String fields are quoted in the text encoding, everything else is parsed with
+/// `FromStr` and written with `Display`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FieldKind {
+    Plain,
+    /// A `&str` borrowed from the line.
+    Borrowed,
+    /// A `String`.
+    Owned,
+    /// A `Vec`, which takes the rest of the line.
+    List,
+}
+
+impl FieldKind {
+    fn of(ty: &Type) -> FieldKind {
+        match ty {
+            Type::Reference(reference) => match &*reference.elem {
+                Type::Path(path) if path.path.is_ident("str") => FieldKind::Borrowed,
+                _ => FieldKind::Plain,
+            },
+            Type::Path(path) => match path.path.segments.last() {
+                Some(segment) if segment.ident == "String" && segment.arguments.is_empty() => {
+                    FieldKind::Owned
+                }
+                Some(segment) if segment.ident == "Vec" => FieldKind::List,
+                _ => FieldKind::Plain,
+            },
+            _ => FieldKind::Plain,
+        }
+    }
+}
+
+/// One option of a `#[burk(...)]` attribute, either `key = value` or just `key`.
+struct BurkArg {
+    key: Ident,
+    value: Option<Expr>,
+}
+
+impl BurkArg {
+    fn value(self) -> syn::Result<Expr> {
+        let key = self.key;
+        self.value
+            .ok_or_else(|| syn::Error::new_spanned(&key, format!("expected `{key} = ...`")))
+    }
+
+    fn flag(self) -> syn::Result<()> {
+        match self.value {
+            Some(value) => Err(syn::Error::new_spanned(
+                value,
+                format!("`{}` does not take a value", self.key),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses the comma separated options of all `#[burk(...)]` attributes.
+fn burk_args(attrs: &[Attribute]) -> syn::Result<Vec<BurkArg>> {
+    let mut args = Vec::new();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("burk")) {
+        let exprs = attr.parse_args_with(Punctuated::<Expr, Token![,]>::parse_terminated)?;
+        for expr in exprs {
+            let (key, value) = match expr {
+                Expr::Assign(assign_expr) => (*assign_expr.left, Some(*assign_expr.right)),
+                expr => (expr, None),
+            };
+            let Some(ident) = (match &key {
+                Expr::Path(path) => path.path.get_ident().cloned(),
+                _ => None,
+            }) else {
+                return Err(syn::Error::new_spanned(key, "expected an option name"));
+            };
+            args.push(BurkArg { key: ident, value });
+        }
+    }
+    Ok(args)
+}
+
+fn str_lit(value: Expr) -> syn::Result<LitStr> {
+    match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit), ..
+        }) => Ok(lit),
+        _ => Err(syn::Error::new_spanned(value, "expected a string literal")),
+    }
+}
+
+fn parse_variant(variant: &syn::Variant) -> syn::Result<BurkVariant<'_>> {
+    let mut custom_name = None;
+    let mut aliases = Vec::new();
+    let mut since = 0;
+    let mut reply = None;
+    for arg in burk_args(&variant.attrs)? {
+        match arg.key.to_string().as_str() {
+            "name" => custom_name = Some(str_lit(arg.value()?)?),
+            "alias" => aliases.push(str_lit(arg.value()?)?),
+            "since" => {
+                let value = arg.value()?;
+                since = match &value {
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Int(lit), ..
+                    }) => lit.base10_parse()?,
+                    _ => return Err(syn::Error::new_spanned(value, "expected a version number")),
+                };
+            }
+            "reply" => {
+                let lit = str_lit(arg.value()?)?;
+                let ident = lit
+                    .parse::<Ident>()
+                    .map_err(|_| syn::Error::new_spanned(&lit, "expected a variant name"))?;
+                reply = Some(ident);
+            }
+            _ => return Err(syn::Error::new_spanned(arg.key, "unknown variant option")),
+        }
+    }
+
+    let mut fields = Vec::new();
+    for field in &variant.fields {
+        let mut default = None;
+        for arg in burk_args(&field.attrs)? {
+            match arg.key.to_string().as_str() {
+                "default" => default = Some(arg.value()?),
+                _ => return Err(syn::Error::new_spanned(arg.key, "unknown field option")),
+            }
+        }
+        if fields.iter().any(|f: &BurkField| f.kind == FieldKind::List) {
+            return Err(syn::Error::new_spanned(
+                field,
+                "a `Vec` field takes the rest of the line, it must be the last field",
+            ));
+        }
+        if default.is_none() && fields.iter().any(|f: &BurkField| f.default.is_some()) {
+            return Err(syn::Error::new_spanned(
+                field,
+                "fields following a field with a default must also have a default",
+            ));
+        }
+        fields.push(BurkField {
+            field,
+            kind: FieldKind::of(&field.ty),
+            default,
+        });
+    }
+
+    let (cmd_name, cmd_span) = match custom_name {
+        Some(lit) => (lit.value(), lit.span()),
+        None => (
+            variant.ident.to_string().to_uppercase(),
+            variant.ident.span(),
+        ),
+    };
+    Ok(BurkVariant {
+        variant,
+        cmd_name,
+        cmd_span,
+        aliases,
+        since,
+        reply,
+        fields,
+    })
+}
+
+#[proc_macro_derive(Burk, attributes(burk))]
+pub fn derive(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    expand(ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(ast: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    // println!("{:#?}", &ast);
+    let syn::Data::Enum(en) = &ast.data else {
+        return Err(syn::Error::new_spanned(
+            &ast.ident,
+            "'burk' can only be derived on enums",
+        ));
+    };
+    let variants = en
+        .variants
+        .iter()
+        .map(parse_variant)
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let mut binary = false;
+    let mut case_insensitive = false;
+    for arg in burk_args(&ast.attrs)? {
+        match arg.key.to_string().as_str() {
+            "binary" => {
+                arg.flag()?;
+                binary = true;
+            }
+            "case_insensitive" => {
+                arg.flag()?;
+                case_insensitive = true;
+            }
+            _ => return Err(syn::Error::new_spanned(arg.key, "unknown enum option")),
+        }
+    }
+
+    let key = |name: &str| match case_insensitive {
+        true => name.to_ascii_lowercase(),
+        false => name.to_owned(),
+    };
+    let mut used = Vec::new();
+    for variant in &variants {
+        for (name, span) in variant.names() {
+            if let Some((_, other)) = used.iter().find(|(used, _)| *used == key(&name)) {
+                return Err(syn::Error::new(
+                    span,
+                    format!("command name {name:?} is already used by `{other}`"),
+                ));
+            }
+            used.push((key(&name), &variant.variant.ident));
+        }
+    }
+
+    if ast.generics.lifetimes().count() > 1 {
+        return Err(syn::Error::new_spanned(
+            &ast.generics,
+            "'burk' enums can have at most one lifetime",
+        ));
+    }
+
+    let mut code = text_codec(&ast, &variants, case_insensitive);
+    code.extend(schema(&ast, &variants, binary));
+    code.extend(reply_impls(&ast, &variants));
+    code.extend(request_helpers(&ast, &variants));
+    if binary {
+        code.extend(binary_codec(&ast, &variants)?);
+    }
+    Ok(code)
+}
+
+/// A pattern matching the variant that binds its fields to `f0`, `f1`, ...
+fn variant_pattern(variant: &BurkVariant) -> (proc_macro2::TokenStream, Vec<Ident>) {
+    let ident = &variant.variant.ident;
+    let field_idents: Vec<_> = (0..variant.fields.len())
+        .map(|cnt| format_ident!("f{cnt}"))
+        .collect();
+    let pattern = match &variant.variant.fields {
+        Fields::Named(_) => {
+            let names = variant.fields.iter().map(|f| &f.field.ident);
+            quote! { Self::#ident { #(#names: #field_idents),* } }
+        }
+        Fields::Unnamed(_) => quote! { Self::#ident (#(#field_idents),*) },
+        Fields::Unit => quote! { Self::#ident },
+    };
+    (pattern, field_idents)
+}
+
+/// An expression constructing the variant, `value` gives the expression for the field
+/// with the given index.
+fn variant_constructor(
+    variant: &BurkVariant,
+    value: impl Fn(usize, &BurkField) -> proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let ident = &variant.variant.ident;
+    let mut construct_code = quote! {};
+    for (idx, field) in variant.fields.iter().enumerate() {
+        let value = value(idx, field);
+        if let Some(field_ident) = &field.field.ident {
+            construct_code.extend(quote! { #field_ident: #value, });
+        } else {
+            construct_code.extend(quote! { #value, });
+        }
+    }
+    match &variant.variant.fields {
+        Fields::Named(_) => quote! { Self::#ident { #construct_code } },
+        Fields::Unnamed(_) => quote! { Self::#ident ( #construct_code ) },
+        Fields::Unit => quote! { Self::#ident },
+    }
+}
+
+fn text_codec(
+    ast: &DeriveInput,
+    variants: &[BurkVariant],
+    case_insensitive: bool,
+) -> proc_macro2::TokenStream {
+    let enum_name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let mut match_code = quote! {};
+    for variant in variants {
+        let names = variant.names().map(|(name, _)| name);
+        let pattern = match case_insensitive {
+            true => quote! { Some(word) if #(word.eq_ignore_ascii_case(#names))||* },
+            false => quote! { Some(#(#names)|*) },
+        };
+        let construct_code = variant_constructor(variant, |idx, field| {
+            let BurkField {
+                field,
+                kind,
+                default,
+            } = field;
+            let ty = &field.ty;
+            let bad_field = quote! { ::burktelefon::text::ParseError::BadField(#idx) };
+            let value = match kind {
+                FieldKind::Plain => quote! {
+                    token
+                        .word()
+                        .and_then(|word| word.parse::<#ty>().ok())
+                        .ok_or(#bad_field)?
+                },
+                FieldKind::Borrowed => quote! { token.as_str().ok_or(#bad_field)? },
+                FieldKind::Owned => quote! { token.unescape().ok_or(#bad_field)? },
+                FieldKind::List => {
+                    return quote! {
+                        ::burktelefon::text::parse_list(&mut parts).ok_or(#bad_field)?
+                    };
+                }
+            };
+            let missing = match default {
+                Some(default) => quote! { #default },
+                None => quote! {
+                    return Err(::burktelefon::text::ParseError::MissingField(#idx))
+                },
+            };
+            quote! {
+                match parts.next() {
+                    Some(token) => #value,
+                    None => #missing,
+                }
+            }
+        });
+        match_code.extend(quote! {
+            #pattern => {
+                core::result::Result::Ok(#construct_code)
+            },
+        });
+    }
+
+    let mut match_fmt = quote! {};
+    for variant in variants {
+        let cmd_name = &variant.cmd_name;
+        let (pattern, field_idents) = variant_pattern(variant);
+        let format_string: String = std::iter::repeat_n("{}", field_idents.len() + 1)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let args = variant
+            .fields
+            .iter()
+            .zip(&field_idents)
+            .map(|(field, ident)| match field.kind {
+                FieldKind::Plain => quote! { #ident },
+                FieldKind::Borrowed | FieldKind::Owned => quote! {
+                    ::burktelefon::text::Quoted(::core::convert::AsRef::<str>::as_ref(#ident))
+                },
+                FieldKind::List => quote! { ::burktelefon::text::List(#ident) },
+            });
+        match_fmt.extend(quote! {
+            #pattern => {
+                write!(f, #format_string, #cmd_name, #(#args),*)
+            }
+        });
+    }
+
+    let parse_body = quote! {
+        let mut parts = ::burktelefon::text::tokens(s);
+        match parts.next().and_then(::burktelefon::text::Token::word) {
+            #match_code
+            _ => Err(::burktelefon::text::ParseError::UnknownCommand),
+        }
+    };
+    // Enums with borrowed fields can't implement `FromStr`, they get an inherent
+    // `parse` tied to the lifetime of the line instead.
+    let parse_code = match ast.generics.lifetimes().next() {
+        Some(lifetime) => {
+            let lifetime = &lifetime.lifetime;
+            quote! {
+                impl #impl_generics #enum_name #ty_generics #where_clause {
+                    pub fn parse(s: &#lifetime str) -> Result<Self, ::burktelefon::text::ParseError> {
+                        #parse_body
+                    }
+                }
+            }
+        }
+        None => quote! {
+            impl #impl_generics core::str::FromStr for #enum_name #ty_generics #where_clause {
+                type Err = ::burktelefon::text::ParseError;
+                fn from_str(s: &str) -> Result<Self, Self::Err>  {
+                    #parse_body
+                }
+            }
+        },
+    };
+
+    quote! {
+        #parse_code
+        impl #impl_generics core::fmt::Display for #enum_name #ty_generics #where_clause {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                match self {
+                    #match_fmt
+                }
+            }
+        }
+    }
+}
+
+fn schema(ast: &DeriveInput, variants: &[BurkVariant], binary: bool) -> proc_macro2::TokenStream {
+    let enum_name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let variant_schemas = variants.iter().map(|variant| {
+        let name = &variant.cmd_name;
+        let ident = variant.variant.ident.to_string();
+        let fields = variant
+            .fields
+            .iter()
+            .map(|BurkField { field, default, .. }| {
+                let ident = match &field.ident {
+                    Some(ident) => {
+                        let ident = ident.to_string();
+                        quote! { Some(#ident) }
+                    }
+                    None => quote! { None },
+                };
+                let ty = &field.ty;
+                // Only the fields of binary enums are known to implement `Field`.
+                let schema = match binary {
+                    true => quote! { <#ty as ::burktelefon::frame::Field>::SCHEMA },
+                    false => quote! { None },
+                };
+                let ty = ty.to_token_stream().to_string();
+                let has_default = default.is_some();
+                quote! {
+                    ::burktelefon::schema::FieldSchema {
+                        ident: #ident,
+                        ty: #ty,
+                        has_default: #has_default,
+                        schema: #schema,
+                    }
+                }
+            });
+        let aliases = &variant.aliases;
+        let since = variant.since;
+        quote! {
+            ::burktelefon::schema::VariantSchema {
+                name: #name,
+                aliases: &[#(#aliases),*],
+                ident: #ident,
+                fields: &[#(#fields),*],
+                since: #since,
+            }
+        }
+    });
+    let schema_arms = variants.iter().enumerate().map(|(idx, variant)| {
+        let ident = &variant.variant.ident;
+        quote! { Self::#ident { .. } => &Self::SCHEMA.variants[#idx], }
+    });
+
+    quote! {
+        impl #impl_generics #enum_name #ty_generics #where_clause {
+            /// Describes the protocol, see [`burktelefon::schema::Schema`].
+            pub const SCHEMA: ::burktelefon::schema::Schema = ::burktelefon::schema::Schema {
+                variants: &[#(#variant_schemas),*],
+            };
+
+            /// The description of this variant in [`Self::SCHEMA`].
+            pub fn variant_schema(&self) -> &'static ::burktelefon::schema::VariantSchema {
+                match self {
+                    #(#schema_arms)*
+                }
+            }
+        }
+    }
+}
+
+/// Implements `Reply` for every variant so that it can be the reply of a command.
+fn reply_impls(ast: &DeriveInput, variants: &[BurkVariant]) -> proc_macro2::TokenStream {
+    let enum_name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let mut code = quote! {};
+    for variant in variants {
+        let ident = variant.variant.ident.to_string();
+        let (pattern, field_idents) = variant_pattern(variant);
+        let tys = variant.fields.iter().map(|f| &f.field.ty);
+        let (fields_ty, fields_value) = match field_idents.as_slice() {
+            [field_ident] => (quote! { #(#tys)* }, quote! { #field_ident }),
+            _ => (quote! { (#(#tys,)*) }, quote! { (#(#field_idents,)*) }),
+        };
+        code.extend(quote! {
+            impl #impl_generics ::burktelefon::request::Reply<
+                { ::burktelefon::request::reply_id(#ident) }
+            > for #enum_name #ty_generics #where_clause {
+                type Fields = #fields_ty;
+                fn into_fields(self) -> Result<Self::Fields, Self> {
+                    match self {
+                        #pattern => Ok(#fields_value),
+                        #[allow(unreachable_patterns)]
+                        other => Err(other),
+                    }
+                }
+            }
+        });
+    }
+    code
+}
+
+/// Generates `request_<variant>` for the variants that have a `reply`.
+fn request_helpers(ast: &DeriveInput, variants: &[BurkVariant]) -> proc_macro2::TokenStream {
+    let enum_name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let mut helpers = quote! {};
+    for variant in variants {
+        let Some(reply) = &variant.reply else {
+            continue;
+        };
+        let ident = &variant.variant.ident;
+        let reply_name = reply.to_string();
+        let fn_name = format_ident!("request_{}", snake_case(&ident.to_string()));
+        let doc = format!("Sends [`Self::{ident}`] and returns the fields of the `{reply}` reply.");
+        let params: Vec<_> = variant
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(idx, f)| match &f.field.ident {
+                Some(name) => name.clone(),
+                None => format_ident!("f{idx}"),
+            })
+            .collect();
+        let tys = variant.fields.iter().map(|f| &f.field.ty);
+        let command = variant_constructor(variant, |idx, _| {
+            let param = &params[idx];
+            quote! { #param }
+        });
+        helpers.extend(quote! {
+            #[doc = #doc]
+            pub fn #fn_name<R, L>(
+                link: &mut L,
+                #(#params: #tys),*
+            ) -> Result<
+                <R as ::burktelefon::request::Reply<
+                    { ::burktelefon::request::reply_id(#reply_name) }
+                >>::Fields,
+                ::burktelefon::request::RequestError<L::Error, R>,
+            >
+            where
+                R: ::burktelefon::request::Reply<{ ::burktelefon::request::reply_id(#reply_name) }>,
+                L: ::burktelefon::request::Link<Self, R> + ?Sized,
+            {
+                ::burktelefon::request::request::<
+                    Self,
+                    R,
+                    L,
+                    { ::burktelefon::request::reply_id(#reply_name) },
+                >(link, &#command)
+            }
+        });
+    }
+    if helpers.is_empty() {
+        return helpers;
+    }
+    quote! {
+        impl #impl_generics #enum_name #ty_generics #where_clause {
+            #helpers
+        }
+    }
+}
+
+fn snake_case(ident: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in ident.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+fn binary_codec(
+    ast: &DeriveInput,
+    variants: &[BurkVariant],
+) -> syn::Result<proc_macro2::TokenStream> {
+    let enum_name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    if let Some(field) = variants
+        .iter()
+        .flat_map(|variant| &variant.fields)
+        .find(|field| field.kind == FieldKind::Borrowed)
+    {
+        return Err(syn::Error::new_spanned(
+            &field.field.ty,
+            "borrowed fields are not supported by the binary codec",
+        ));
+    }
+
+    let mut encode_arms = quote! {};
+    let mut decode_arms = quote! {};
+    for (tag, variant) in variants.iter().enumerate() {
+        // The last tag is `SEQ_TAG`.
+        let Some(tag) = u8::try_from(tag).ok().filter(|&tag| tag != u8::MAX) else {
+            return Err(syn::Error::new_spanned(
+                &variant.variant.ident,
+                "binary enums can have at most 255 variants",
+            ));
+        };
+        let tag = Literal::u8_suffixed(tag);
+
+        let (pattern, field_idents) = variant_pattern(variant);
+        encode_arms.extend(quote! {
+            #pattern => {
+                ::burktelefon::frame::Field::write(&#tag, w)?;
+                #(::burktelefon::frame::Field::write(#field_idents, w)?;)*
+            }
+        });
+
+        let construct_code =
+            variant_constructor(variant, |_, BurkField { field, default, .. }| {
+                let ty = &field.ty;
+                let read = quote! { <#ty as ::burktelefon::frame::Field>::read(r)? };
+                if let Some(default) = default {
+                    quote! { if r.is_empty() { #default } else { #read } }
+                } else {
+                    read
+                }
+            });
+        decode_arms.extend(quote! {
+            #tag => core::result::Result::Ok(#construct_code),
+        });
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::burktelefon::frame::Binary for #enum_name #ty_generics #where_clause {
+            const SCHEMA: Option<&'static ::burktelefon::schema::Schema> = Some(&Self::SCHEMA);
+
+            fn encode(
+                &self,
+                w: &mut ::burktelefon::frame::Writer,
+            ) -> Result<(), ::burktelefon::frame::FrameError> {
+                match self {
+                    #encode_arms
+                }
+                Ok(())
+            }
+            fn decode(
+                r: &mut ::burktelefon::frame::Reader,
+            ) -> Result<Self, ::burktelefon::frame::FrameError> {
+                match <u8 as ::burktelefon::frame::Field>::read(r)? {
+                    #decode_arms
+                    tag => Err(::burktelefon::frame::FrameError::UnknownTag(tag)),
+                }
+            }
+        }
+    })
+}
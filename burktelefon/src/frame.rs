@@ -0,0 +1,352 @@
+//! The binary encoding.
+//!
+//! A value is encoded as a tag byte, which is the index of the variant, followed
+//! by its fields in little-endian and a CRC-16 of everything before it. That payload
+//! is COBS encoded so that it contains no zero bytes and is written with a zero byte
+//! on each side, which lets a receiver that is reading text lines tell frames apart.
+//!
+//! Since the tag is the index of the variant, new variants must be added last.
+//!
+//! A frame may carry a sequence id, then the payload starts with [`SEQ_TAG`] and the
+//! id as a `u16` before the tag of the value.
+
+use core::fmt;
+
+use crate::schema::Schema;
+
+/// The max length of a payload, including the tag and the CRC.
+pub const MAX_PAYLOAD_LEN: usize = 254;
+
+/// The max length of an encoded frame, including both delimiters.
+pub const MAX_FRAME_LEN: usize = MAX_PAYLOAD_LEN + MAX_PAYLOAD_LEN / 254 + 1 + 2;
+
+/// Marks a payload that starts with a sequence id, no variant has this tag.
+pub const SEQ_TAG: u8 = 0xff;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// The value or the frame does not fit in the buffer.
+    BufferTooSmall,
+    /// The frame is not valid COBS.
+    Cobs,
+    /// The CRC does not match, the frame was corrupted.
+    Crc,
+    /// The frame ended before all fields were read.
+    Truncated,
+    /// No variant has this tag.
+    UnknownTag(u8),
+    /// A field has a value its type can't hold.
+    BadValue,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::BufferTooSmall => write!(f, "buffer too small"),
+            FrameError::Cobs => write!(f, "invalid COBS encoding"),
+            FrameError::Crc => write!(f, "CRC mismatch"),
+            FrameError::Truncated => write!(f, "frame truncated"),
+            FrameError::UnknownTag(tag) => write!(f, "unknown tag {tag}"),
+            FrameError::BadValue => write!(f, "bad field value"),
+        }
+    }
+}
+
+impl core::error::Error for FrameError {}
+
+/// Implemented by `#[derive(Burk)]` on enums marked with `#[burk(binary)]`.
+pub trait Binary: Sized {
+    /// The `SCHEMA` of the enum, see [`Field::SCHEMA`].
+    const SCHEMA: Option<&'static Schema> = None;
+
+    /// Writes the tag and the fields.
+    fn encode(&self, w: &mut Writer) -> Result<(), FrameError>;
+
+    /// Reads the tag and the fields.
+    fn decode(r: &mut Reader) -> Result<Self, FrameError>;
+
+    /// Encodes a complete frame into `buf` and returns its length.
+    fn to_frame(&self, buf: &mut [u8]) -> Result<usize, FrameError> {
+        write_frame(buf, None, |w| self.encode(w))
+    }
+
+    /// Encodes a complete frame with a sequence id into `buf` and returns its length.
+    fn to_frame_seq(&self, seq: u16, buf: &mut [u8]) -> Result<usize, FrameError> {
+        write_frame(buf, Some(seq), |w| self.encode(w))
+    }
+
+    /// Decodes a frame, with or without its delimiters. The sequence id is skipped, see
+    /// [`frame_seq`].
+    fn from_frame(frame: &[u8]) -> Result<Self, FrameError> {
+        let mut payload = [0; MAX_PAYLOAD_LEN];
+        let mut r = read_frame(frame, &mut payload)?;
+        if r.buf.first() == Some(&SEQ_TAG) {
+            r.read_bytes(3)?;
+        }
+        // Trailing bytes are ignored, they are fields added by a newer version.
+        Self::decode(&mut r)
+    }
+}
+
+/// Returns the sequence id of a valid frame, even if its value can't be decoded.
+pub fn frame_seq(frame: &[u8]) -> Option<u16> {
+    let mut payload = [0; MAX_PAYLOAD_LEN];
+    let mut r = read_frame(frame, &mut payload).ok()?;
+    if u8::read(&mut r).ok()? != SEQ_TAG {
+        return None;
+    }
+    u16::read(&mut r).ok()
+}
+
+fn write_frame(
+    buf: &mut [u8],
+    seq: Option<u16>,
+    encode: impl FnOnce(&mut Writer) -> Result<(), FrameError>,
+) -> Result<usize, FrameError> {
+    let mut payload = [0; MAX_PAYLOAD_LEN];
+    let mut w = Writer::new(&mut payload);
+    if let Some(seq) = seq {
+        SEQ_TAG.write(&mut w)?;
+        seq.write(&mut w)?;
+    }
+    encode(&mut w)?;
+    let crc = crc16(w.written());
+    crc.write(&mut w)?;
+    let len = w.len();
+
+    let (first, rest) = buf.split_first_mut().ok_or(FrameError::BufferTooSmall)?;
+    *first = 0;
+    let n = cobs_encode(&payload[..len], rest).ok_or(FrameError::BufferTooSmall)?;
+    *rest.get_mut(n).ok_or(FrameError::BufferTooSmall)? = 0;
+    Ok(n + 2)
+}
+
+/// Decodes the COBS encoding into `payload`, checks the CRC and returns a reader of
+/// what is before it.
+fn read_frame<'a>(frame: &[u8], payload: &'a mut [u8]) -> Result<Reader<'a>, FrameError> {
+    let start = frame.iter().position(|&b| b != 0).unwrap_or(frame.len());
+    let end = frame.iter().rposition(|&b| b != 0).map_or(start, |i| i + 1);
+
+    let len = cobs_decode(&frame[start..end], payload).ok_or(FrameError::Cobs)?;
+    if len < 2 {
+        return Err(FrameError::Truncated);
+    }
+    let (body, crc) = payload[..len].split_at(len - 2);
+    if crc16(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+        return Err(FrameError::Crc);
+    }
+    Ok(Reader::new(body))
+}
+
+/// A type that can be a field of a binary encoded variant.
+pub trait Field: Sized {
+    /// Describes the values if they are variants of an enum, so that changing them
+    /// changes the hash of the enums they are fields of, see
+    /// [`FieldSchema::schema`](crate::schema::FieldSchema::schema).
+    const SCHEMA: Option<&'static Schema> = None;
+
+    fn write(&self, w: &mut Writer) -> Result<(), FrameError>;
+    fn read(r: &mut Reader) -> Result<Self, FrameError>;
+}
+
+macro_rules! impl_field_le {
+    ($($ty:ty),*) => {
+        $(
+            impl Field for $ty {
+                fn write(&self, w: &mut Writer) -> Result<(), FrameError> {
+                    w.write_bytes(&self.to_le_bytes())
+                }
+                fn read(r: &mut Reader) -> Result<Self, FrameError> {
+                    let bytes = r.read_bytes(core::mem::size_of::<$ty>())?;
+                    Ok(<$ty>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+impl_field_le!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl Field for bool {
+    fn write(&self, w: &mut Writer) -> Result<(), FrameError> {
+        w.write_bytes(&[*self as u8])
+    }
+    fn read(r: &mut Reader) -> Result<Self, FrameError> {
+        match u8::read(r)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(FrameError::BadValue),
+        }
+    }
+}
+
+/// Binary enums can be fields of other binary enums, which lets a response carry for
+/// example an error code.
+impl<T: Binary> Field for T {
+    const SCHEMA: Option<&'static Schema> = T::SCHEMA;
+
+    fn write(&self, w: &mut Writer) -> Result<(), FrameError> {
+        self.encode(w)
+    }
+    fn read(r: &mut Reader) -> Result<Self, FrameError> {
+        T::decode(r)
+    }
+}
+
+/// Strings are prefixed with their length as one byte.
+#[cfg(feature = "alloc")]
+impl Field for alloc::string::String {
+    fn write(&self, w: &mut Writer) -> Result<(), FrameError> {
+        let len = u8::try_from(self.len()).map_err(|_| FrameError::BufferTooSmall)?;
+        len.write(w)?;
+        w.write_bytes(self.as_bytes())
+    }
+    fn read(r: &mut Reader) -> Result<Self, FrameError> {
+        let len = u8::read(r)?;
+        let bytes = r.read_bytes(len as usize)?;
+        let s = core::str::from_utf8(bytes).map_err(|_| FrameError::BadValue)?;
+        Ok(s.into())
+    }
+}
+
+/// Lists are prefixed with their number of elements as one byte.
+#[cfg(feature = "alloc")]
+impl<T: Field> Field for alloc::vec::Vec<T> {
+    const SCHEMA: Option<&'static Schema> = T::SCHEMA;
+
+    fn write(&self, w: &mut Writer) -> Result<(), FrameError> {
+        let len = u8::try_from(self.len()).map_err(|_| FrameError::BufferTooSmall)?;
+        len.write(w)?;
+        self.iter().try_for_each(|item| item.write(w))
+    }
+    fn read(r: &mut Reader) -> Result<Self, FrameError> {
+        let len = u8::read(r)?;
+        (0..len).map(|_| T::read(r)).collect()
+    }
+}
+
+/// Writes fields into a payload buffer.
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Writer { buf, len: 0 }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), FrameError> {
+        let dst = self
+            .buf
+            .get_mut(self.len..self.len + bytes.len())
+            .ok_or(FrameError::BufferTooSmall)?;
+        dst.copy_from_slice(bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn written(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Reads fields from a payload.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf }
+    }
+
+    pub fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], FrameError> {
+        if n > self.buf.len() {
+            return Err(FrameError::Truncated);
+        }
+        let (bytes, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    /// True if there are no fields left, used for fields with defaults.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+/// CRC-16/CCITT-FALSE.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffff_u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// COBS encodes `data` into `out` and returns the encoded length, or `None` if it
+/// doesn't fit. The delimiting zero byte is not written.
+pub fn cobs_encode(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut code_index = 0;
+    let mut code = 1_u8;
+    let mut len = 1;
+    for &byte in data {
+        if byte != 0 {
+            *out.get_mut(len)? = byte;
+            len += 1;
+            code += 1;
+        }
+        if byte == 0 || code == 0xff {
+            *out.get_mut(code_index)? = code;
+            code_index = len;
+            len += 1;
+            code = 1;
+        }
+    }
+    *out.get_mut(code_index)? = code;
+    Some(len)
+}
+
+/// Decodes COBS encoded `data` without delimiters into `out` and returns the decoded
+/// length, or `None` if the encoding is invalid or doesn't fit.
+pub fn cobs_decode(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut i = 0;
+    let mut len = 0;
+    while i < data.len() {
+        let code = data[i];
+        if code == 0 {
+            return None;
+        }
+        i += 1;
+        for _ in 1..code {
+            let byte = *data.get(i)?;
+            if byte == 0 {
+                return None;
+            }
+            *out.get_mut(len)? = byte;
+            len += 1;
+            i += 1;
+        }
+        if code != 0xff && i < data.len() {
+            *out.get_mut(len)? = 0;
+            len += 1;
+        }
+    }
+    Some(len)
+}
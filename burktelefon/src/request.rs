@@ -0,0 +1,67 @@
+//! Typed requests, generated for variants marked with `#[burk(reply = "...")]`.
+//!
+//! A command variant marked with `#[burk(reply = "Position")]` gets a helper
+//! `request_<variant>` that sends the command over a [`Link`] and returns the fields of
+//! the `Position` variant of the response enum. Any other response is an error instead
+//! of something the caller has to match on.
+
+use core::fmt;
+
+/// A connection that sends `C` and receives `R`.
+pub trait Link<C, R> {
+    type Error;
+
+    fn send(&mut self, command: &C) -> Result<(), Self::Error>;
+
+    /// Receives the next value, waiting for it if needed.
+    fn receive(&mut self) -> Result<R, Self::Error>;
+}
+
+/// Implemented by `#[derive(Burk)]` for every variant, `ID` is [`reply_id`] of the
+/// variant name.
+pub trait Reply<const ID: u32>: Sized {
+    /// `()` for unit variants, the field for variants with one field and a tuple of
+    /// the fields otherwise.
+    type Fields;
+
+    /// Returns the fields, or `self` if it is another variant.
+    fn into_fields(self) -> Result<Self::Fields, Self>;
+}
+
+/// Identifies a variant by its name in the enum.
+pub const fn reply_id(ident: &str) -> u32 {
+    crate::schema::fnv1a(crate::schema::FNV_OFFSET, ident.as_bytes())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestError<E, R> {
+    /// Sending or receiving failed.
+    Link(E),
+    /// The reply was another variant than the expected one.
+    Unexpected(R),
+}
+
+impl<E: fmt::Display, R: fmt::Debug> fmt::Display for RequestError<E, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Link(e) => write!(f, "{e}"),
+            RequestError::Unexpected(reply) => write!(f, "unexpected reply {reply:?}"),
+        }
+    }
+}
+
+impl<E: fmt::Display + fmt::Debug, R: fmt::Debug> core::error::Error for RequestError<E, R> {}
+
+/// Sends `command` and waits for the reply `ID`, used by the generated helpers.
+pub fn request<C, R, L, const ID: u32>(
+    link: &mut L,
+    command: &C,
+) -> Result<R::Fields, RequestError<L::Error, R>>
+where
+    R: Reply<ID>,
+    L: Link<C, R> + ?Sized,
+{
+    link.send(command).map_err(RequestError::Link)?;
+    let reply = link.receive().map_err(RequestError::Link)?;
+    reply.into_fields().map_err(RequestError::Unexpected)
+}
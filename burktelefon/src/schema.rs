@@ -0,0 +1,151 @@
+//! Descriptions of the protocols, generated as `SCHEMA` by `#[derive(Burk)]`.
+
+use core::fmt;
+
+/// Describes all variants of an enum, in declaration order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Schema {
+    pub variants: &'static [VariantSchema],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VariantSchema {
+    /// The command name used on the wire.
+    pub name: &'static str,
+    /// Other names that are accepted when parsing text.
+    pub aliases: &'static [&'static str],
+    /// The name of the variant in the enum.
+    pub ident: &'static str,
+    pub fields: &'static [FieldSchema],
+    /// The protocol version that added the variant, from `#[burk(since = N)]`.
+    pub since: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FieldSchema {
+    /// The field name, `None` for tuple variants.
+    pub ident: Option<&'static str>,
+    /// The type as written in the enum.
+    pub ty: &'static str,
+    pub has_default: bool,
+    /// The schema of the type if it is a binary enum itself, whose variants are part of
+    /// the wire format too. `None` in enums without `#[burk(binary)]`.
+    pub schema: Option<&'static Schema>,
+}
+
+impl Schema {
+    /// Looks up a variant by its command name or one of its aliases.
+    pub fn variant(&self, name: &str) -> Option<&VariantSchema> {
+        self.variants
+            .iter()
+            .find(|variant| variant.name == name || variant.aliases.contains(&name))
+    }
+
+    /// The newest protocol version, the largest `since` of the variants and of the
+    /// variants of the enums in their fields.
+    pub const fn version(&self) -> u32 {
+        let mut version = 0;
+        let mut i = 0;
+        while i < self.variants.len() {
+            let variant = &self.variants[i];
+            if variant.since > version {
+                version = variant.since;
+            }
+            let mut j = 0;
+            while j < variant.fields.len() {
+                if let Some(schema) = variant.fields[j].schema {
+                    let nested = schema.version();
+                    if nested > version {
+                        version = nested;
+                    }
+                }
+                j += 1;
+            }
+            i += 1;
+        }
+        version
+    }
+
+    /// A hash of everything that affects the wire format, that is the command names,
+    /// the order of the variants and the field types, including the variants of enums
+    /// in the fields. Renaming variants or fields does not change it.
+    pub const fn hash(&self) -> u32 {
+        self.hash_up_to(u32::MAX)
+    }
+
+    /// The hash of the protocol as it was in `version`, leaving out the variants that
+    /// were added after it. Comparing it with the hash reported by the other side tells
+    /// which version the other side speaks.
+    pub const fn hash_up_to(&self, version: u32) -> u32 {
+        let mut hash = FNV_OFFSET;
+        let mut i = 0;
+        while i < self.variants.len() {
+            let variant = &self.variants[i];
+            if variant.since > version {
+                i += 1;
+                continue;
+            }
+            hash = fnv1a(hash, variant.name.as_bytes());
+            let mut j = 0;
+            while j < variant.fields.len() {
+                let field = &variant.fields[j];
+                hash = fnv1a(hash, field.ty.as_bytes());
+                if let Some(schema) = field.schema {
+                    hash = fnv1a(hash, &schema.hash_up_to(version).to_le_bytes());
+                }
+                j += 1;
+            }
+            hash = fnv1a(hash, b"\n");
+            i += 1;
+        }
+        hash
+    }
+}
+
+pub(crate) const FNV_OFFSET: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// Hashes `bytes` followed by a separator that can't be part of a name or a type.
+pub(crate) const fn fnv1a(mut hash: u32, bytes: &[u8]) -> u32 {
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(FNV_PRIME);
+        i += 1;
+    }
+    hash ^= 0xff;
+    hash.wrapping_mul(FNV_PRIME)
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for variant in self.variants {
+            writeln!(f, "{variant}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for VariantSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for alias in self.aliases {
+            write!(f, "|{alias}")?;
+        }
+        write!(f, ": {}", self.ident)?;
+        for field in self.fields {
+            write!(f, " ")?;
+            if let Some(ident) = field.ident {
+                write!(f, "{ident}=")?;
+            }
+            write!(f, "{}", field.ty)?;
+            if field.has_default {
+                write!(f, "?")?;
+            }
+        }
+        if self.since > 0 {
+            write!(f, " (since {})", self.since)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+//! The text encoding, one whitespace separated line per value.
+//!
+//! String fields are written in double quotes, see [`Quoted`], so they may contain
+//! whitespace.
+//!
+//! A `Vec` field is written as its elements separated by whitespace and takes the rest
+//! of the line, see [`parse_list`].
+//!
+//! A line may start with a sequence id such as `@12 q 90 45 0`, see [`split_seq`].
+
+use core::fmt::{self, Write};
+
+/// The error of the generated `FromStr` implementations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The line is empty or starts with an unknown command name.
+    UnknownCommand,
+    /// The line ended before the field with this index.
+    MissingField(usize),
+    /// The field with this index could not be parsed.
+    BadField(usize),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownCommand => write!(f, "unknown command"),
+            ParseError::MissingField(idx) => write!(f, "missing field {idx}"),
+            ParseError::BadField(idx) => write!(f, "bad field {idx}"),
+        }
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+/// Splits off the sequence id at the start of a line, written as `@<id>`. Returns the
+/// line unchanged if it doesn't have one.
+pub fn split_seq(line: &str) -> (Option<u16>, &str) {
+    let trimmed = line.trim_start();
+    if let Some(rest) = trimmed.strip_prefix('@') {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        if let Ok(seq) = rest[..end].parse() {
+            return (Some(seq), &rest[end..]);
+        }
+    }
+    (None, line)
+}
+
+/// A part of a line, as split by [`tokens`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token<'a> {
+    /// Text without whitespace.
+    Word(&'a str),
+    /// The text between a pair of quotes, with the escapes left as they are.
+    Quoted(&'a str),
+    /// A quote that is never closed.
+    Unterminated,
+}
+
+impl<'a> Token<'a> {
+    /// The text of a word. Used for all fields that aren't strings.
+    pub fn word(self) -> Option<&'a str> {
+        match self {
+            Token::Word(word) => Some(word),
+            _ => None,
+        }
+    }
+
+    /// The text of a word or a quoted string. Used for `&str` fields, which borrow
+    /// from the line and therefore can't hold escaped characters.
+    pub fn as_str(self) -> Option<&'a str> {
+        match self {
+            Token::Word(s) => Some(s),
+            Token::Quoted(s) if !s.contains('\\') => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The text of a word or a quoted string with the escapes resolved. Used for
+    /// `String` fields.
+    #[cfg(feature = "alloc")]
+    pub fn unescape(self) -> Option<alloc::string::String> {
+        let quoted = match self {
+            Token::Word(word) => return Some(word.into()),
+            Token::Quoted(quoted) => quoted,
+            Token::Unterminated => return None,
+        };
+        let mut s = alloc::string::String::with_capacity(quoted.len());
+        let mut chars = quoted.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '\\' {
+                s.push(ch);
+                continue;
+            }
+            s.push(match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                ch @ ('\\' | '"') => ch,
+                _ => return None,
+            });
+        }
+        Some(s)
+    }
+}
+
+/// Splits a line on whitespace, except inside quotes.
+pub fn tokens(line: &str) -> Tokens<'_> {
+    Tokens { rest: line }
+}
+
+pub struct Tokens<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let rest = self.rest.trim_start();
+        if rest.is_empty() {
+            self.rest = rest;
+            return None;
+        }
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut escaped = false;
+            for (i, ch) in quoted.char_indices() {
+                match ch {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => {
+                        self.rest = &quoted[i + 1..];
+                        return Some(Token::Quoted(&quoted[..i]));
+                    }
+                    _ => {}
+                }
+            }
+            self.rest = "";
+            return Some(Token::Unterminated);
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        self.rest = &rest[end..];
+        Some(Token::Word(&rest[..end]))
+    }
+}
+
+/// Displays a string in quotes, escaping quotes, backslashes and line breaks.
+pub struct Quoted<'a>(pub &'a str);
+
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
+        for ch in self.0.chars() {
+            match ch {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                ch => f.write_char(ch)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// Parses the remaining words as the elements of a `Vec` field.
+#[cfg(feature = "alloc")]
+pub fn parse_list<T: core::str::FromStr>(tokens: &mut Tokens) -> Option<alloc::vec::Vec<T>> {
+    tokens.map(|token| token.word()?.parse().ok()).collect()
+}
+
+/// Displays the elements of a `Vec` field separated by spaces.
+pub struct List<'a, T>(pub &'a [T]);
+
+impl<T: fmt::Display> fmt::Display for List<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, item) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_char(' ')?;
+            }
+            write!(f, "{item}")?;
+        }
+        Ok(())
+    }
+}
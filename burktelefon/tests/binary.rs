@@ -0,0 +1,147 @@
+use burktelefon::frame::{
+    cobs_decode, cobs_encode, crc16, frame_seq, Binary, FrameError, MAX_FRAME_LEN,
+};
+use burktelefon::Burk;
+
+#[derive(Burk, Debug, PartialEq)]
+#[burk(binary)]
+pub enum Command {
+    #[burk(name = "q")]
+    Queue {
+        bottom: f32,
+        top: f32,
+        sideways: f32,
+        #[burk(default = 1.0)]
+        speed: f32,
+    },
+    #[burk(name = "iscal")]
+    IsCalibrated(bool),
+    #[burk(name = "qs")]
+    QueueSize(u32, u32),
+    Other,
+}
+
+#[derive(Burk, Debug, PartialEq)]
+#[burk(binary)]
+pub enum Code {
+    #[burk(name = "sensor")]
+    Sensor,
+    #[burk(name = "full")]
+    QueueFull,
+}
+
+#[derive(Burk, Debug, PartialEq)]
+#[burk(binary)]
+pub enum Response {
+    #[burk(name = "err")]
+    Error(Code, u32),
+}
+
+fn roundtrip(cmd: Command) {
+    let mut buf = [0; MAX_FRAME_LEN];
+    let len = cmd.to_frame(&mut buf).unwrap();
+    let frame = &buf[..len];
+    assert_eq!(frame[0], 0);
+    assert_eq!(frame[len - 1], 0);
+    assert!(frame[1..len - 1].iter().all(|&b| b != 0));
+    assert_eq!(Command::from_frame(frame).unwrap(), cmd);
+    assert_eq!(Command::from_frame(&frame[1..len - 1]).unwrap(), cmd);
+}
+
+fn main() {
+    assert_eq!(crc16(b"123456789"), 0x29b1);
+
+    let mut encoded = [0; 8];
+    let mut decoded = [0; 8];
+    let len = cobs_encode(&[0x11, 0x00, 0x00, 0x22], &mut encoded).unwrap();
+    assert_eq!(&encoded[..len], &[0x02, 0x11, 0x01, 0x02, 0x22]);
+    let len = cobs_decode(&encoded[..len], &mut decoded).unwrap();
+    assert_eq!(&decoded[..len], &[0x11, 0x00, 0x00, 0x22]);
+
+    roundtrip(Command::Queue {
+        bottom: 90.0,
+        top: 0.0,
+        sideways: -0.25,
+        speed: 0.5,
+    });
+    roundtrip(Command::IsCalibrated(true));
+    roundtrip(Command::QueueSize(0, 300));
+    roundtrip(Command::Other);
+
+    // Corrupted frames are detected.
+    let mut buf = [0; MAX_FRAME_LEN];
+    let len = Command::QueueSize(12, 300).to_frame(&mut buf).unwrap();
+    buf[3] ^= 0x40;
+    assert_eq!(Command::from_frame(&buf[..len]), Err(FrameError::Crc));
+
+    // Too small buffers are reported instead of truncating the frame.
+    let mut small = [0; 4];
+    assert_eq!(
+        Command::QueueSize(12, 300).to_frame(&mut small),
+        Err(FrameError::BufferTooSmall)
+    );
+
+    // Trailing fields with defaults may be left out by older senders.
+    let mut payload = [0; 16];
+    payload[0] = 0;
+    payload[1..5].copy_from_slice(&90_f32.to_le_bytes());
+    payload[5..9].copy_from_slice(&45_f32.to_le_bytes());
+    payload[9..13].copy_from_slice(&0.1_f32.to_le_bytes());
+    let crc = crc16(&payload[..13]);
+    payload[13..15].copy_from_slice(&crc.to_le_bytes());
+    let len = cobs_encode(&payload[..15], &mut buf).unwrap();
+    assert_eq!(
+        Command::from_frame(&buf[..len]).unwrap(),
+        Command::Queue {
+            bottom: 90.0,
+            top: 45.0,
+            sideways: 0.1,
+            speed: 1.0
+        }
+    );
+
+    // Unknown tags are rejected.
+    let payload = [9, 0, 0];
+    let crc = crc16(&payload[..1]).to_le_bytes();
+    let len = cobs_encode(&[9, crc[0], crc[1]], &mut buf).unwrap();
+    assert_eq!(
+        Command::from_frame(&buf[..len]),
+        Err(FrameError::UnknownTag(9))
+    );
+
+    // Sequence ids are skipped when decoding, and can be read even if the value can't.
+    let len = Command::QueueSize(1, 300)
+        .to_frame_seq(513, &mut buf)
+        .unwrap();
+    assert_eq!(frame_seq(&buf[..len]), Some(513));
+    assert_eq!(
+        Command::from_frame(&buf[..len]).unwrap(),
+        Command::QueueSize(1, 300)
+    );
+    let payload = [0xff, 7, 0, 9];
+    let crc = crc16(&payload).to_le_bytes();
+    let len = cobs_encode(&[0xff, 7, 0, 9, crc[0], crc[1]], &mut buf).unwrap();
+    assert_eq!(frame_seq(&buf[..len]), Some(7));
+    assert_eq!(
+        Command::from_frame(&buf[..len]),
+        Err(FrameError::UnknownTag(9))
+    );
+    let len = Command::Other.to_frame(&mut buf).unwrap();
+    assert_eq!(frame_seq(&buf[..len]), None);
+
+    // Binary enums can be fields.
+    let response = Response::Error(Code::QueueFull, 300);
+    let len = response.to_frame(&mut buf).unwrap();
+    assert_eq!(Response::from_frame(&buf[..len]).unwrap(), response);
+    assert_eq!(response.to_string(), "err full 300");
+    assert_eq!(
+        "err sensor 24".parse(),
+        Ok(Response::Error(Code::Sensor, 24))
+    );
+
+    // The text codec is still there.
+    assert_eq!(
+        "qs 1 300".parse::<Command>().unwrap(),
+        Command::QueueSize(1, 300)
+    );
+}
@@ -0,0 +1,66 @@
+use burktelefon::frame::{Binary, Field, FrameError, Reader, Writer};
+use burktelefon::text::ParseError;
+use burktelefon::Burk;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point(i16, i16);
+
+impl FromStr for Point {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (x, y) = s.split_once(',').ok_or(())?;
+        Ok(Point(
+            x.parse().map_err(|_| ())?,
+            y.parse().map_err(|_| ())?,
+        ))
+    }
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.0, self.1)
+    }
+}
+
+impl Field for Point {
+    fn write(&self, w: &mut Writer) -> Result<(), FrameError> {
+        self.0.write(w)?;
+        self.1.write(w)
+    }
+    fn read(r: &mut Reader) -> Result<Self, FrameError> {
+        Ok(Point(i16::read(r)?, i16::read(r)?))
+    }
+}
+
+#[derive(Burk, Debug, PartialEq)]
+#[burk(binary)]
+pub enum Command {
+    #[burk(name = "path")]
+    Path(u8, Vec<Point>),
+    #[burk(name = "sum")]
+    Sum { values: Vec<u32> },
+}
+
+fn main() {
+    let cmd: Command = "path 2 1,2 -3,4".parse().unwrap();
+    assert_eq!(cmd, Command::Path(2, vec![Point(1, 2), Point(-3, 4)]));
+    assert_eq!(cmd.to_string(), "path 2 1,2 -3,4");
+
+    assert_eq!(
+        "sum".parse::<Command>().unwrap(),
+        Command::Sum { values: vec![] }
+    );
+    assert_eq!("sum 1 x".parse::<Command>(), Err(ParseError::BadField(0)));
+    assert_eq!("path".parse::<Command>(), Err(ParseError::MissingField(0)));
+
+    let mut buf = [0; 64];
+    let n = cmd.to_frame(&mut buf).unwrap();
+    assert_eq!(Command::from_frame(&buf[..n]).unwrap(), cmd);
+
+    let cmd = Command::Sum {
+        values: (0..100).collect(),
+    };
+    assert_eq!(cmd.to_frame(&mut [0; 512]), Err(FrameError::BufferTooSmall));
+}
@@ -1,5 +1,5 @@
+use burktelefon::text::{split_seq, ParseError};
 use burktelefon::Burk;
-use std as alloc;
 
 #[derive(Burk)]
 pub enum Command {
@@ -14,4 +14,26 @@ fn main() {
     println!("{}", cmd);
 
     //println!("{}", Command::Other);
+
+    assert_eq!(
+        "".parse::<Command>().err(),
+        Some(ParseError::UnknownCommand)
+    );
+    assert_eq!(
+        "R 1 2".parse::<Command>().err(),
+        Some(ParseError::UnknownCommand)
+    );
+    assert_eq!(
+        "Q 1".parse::<Command>().err(),
+        Some(ParseError::MissingField(1))
+    );
+    assert_eq!(
+        "Q 1 x".parse::<Command>().err(),
+        Some(ParseError::BadField(1))
+    );
+    assert_eq!(ParseError::BadField(1).to_string(), "bad field 1");
+
+    assert_eq!(split_seq("@12 Q 1 2"), (Some(12), " Q 1 2"));
+    assert_eq!(split_seq("Q 1 2"), (None, "Q 1 2"));
+    assert_eq!(split_seq("@x Q 1 2"), (None, "@x Q 1 2"));
 }
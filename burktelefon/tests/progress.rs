@@ -2,4 +2,12 @@
 fn test() {
     let t = trybuild::TestCases::new();
     t.pass("tests/parse.rs");
+    t.pass("tests/struct_variants.rs");
+    t.pass("tests/binary.rs");
+    t.pass("tests/schema.rs");
+    t.pass("tests/strings.rs");
+    t.pass("tests/request.rs");
+    t.pass("tests/versions.rs");
+    t.pass("tests/lists.rs");
+    t.compile_fail("tests/ui/*.rs");
 }
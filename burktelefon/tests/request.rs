@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+
+use burktelefon::request::{Link, RequestError};
+use burktelefon::Burk;
+
+#[derive(Burk, Debug, PartialEq)]
+pub enum Command {
+    #[burk(name = "pos", reply = "Position")]
+    Position,
+    #[burk(name = "qs", reply = "QueueSize")]
+    QueueSize,
+    #[burk(name = "iscal", reply = "IsCalibrated")]
+    IsCalibrated { axis: u8 },
+    #[burk(name = "grip")]
+    Grip,
+}
+
+#[derive(Burk, Debug, PartialEq)]
+pub enum Response {
+    #[burk(name = "pos")]
+    Position(f32, f32, f32),
+    #[burk(name = "qs")]
+    QueueSize(u32, u32),
+    #[burk(name = "iscal")]
+    IsCalibrated(bool),
+}
+
+/// Answers with canned responses.
+struct Mock {
+    sent: Vec<String>,
+    responses: VecDeque<Response>,
+}
+
+impl Link<Command, Response> for Mock {
+    type Error = &'static str;
+
+    fn send(&mut self, command: &Command) -> Result<(), Self::Error> {
+        self.sent.push(command.to_string());
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Response, Self::Error> {
+        self.responses.pop_front().ok_or("timed out")
+    }
+}
+
+fn main() {
+    let mut link = Mock {
+        sent: Vec::new(),
+        responses: VecDeque::from([
+            Response::Position(1.0, 2.0, 3.0),
+            Response::IsCalibrated(true),
+            Response::Position(1.0, 2.0, 3.0),
+        ]),
+    };
+    assert_eq!(Command::request_position(&mut link), Ok((1.0, 2.0, 3.0)));
+    assert_eq!(Command::request_is_calibrated(&mut link, 2), Ok(true));
+    assert_eq!(
+        Command::request_queue_size(&mut link),
+        Err(RequestError::Unexpected(Response::Position(1.0, 2.0, 3.0)))
+    );
+    assert_eq!(
+        Command::request_position(&mut link),
+        Err(RequestError::Link("timed out"))
+    );
+    assert_eq!(link.sent, ["pos", "iscal 2", "qs", "pos"]);
+}
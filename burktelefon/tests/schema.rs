@@ -0,0 +1,84 @@
+use burktelefon::schema::{FieldSchema, VariantSchema};
+use burktelefon::Burk;
+
+#[derive(Burk)]
+pub enum Command {
+    #[burk(name = "q")]
+    Queue {
+        bottom: f32,
+        #[burk(default = 1.0)]
+        speed: f32,
+    },
+    #[burk(name = "pos")]
+    Position,
+}
+
+mod renamed {
+    use burktelefon::Burk;
+
+    #[derive(Burk)]
+    pub enum Cmd {
+        #[burk(name = "q")]
+        Enqueue { a: f32, b: f32 },
+        #[burk(name = "pos")]
+        Pos,
+    }
+}
+
+mod changed {
+    use burktelefon::Burk;
+
+    #[derive(Burk)]
+    pub enum Command {
+        #[burk(name = "q")]
+        Queue { bottom: f32, speed: f64 },
+        #[burk(name = "pos")]
+        Position,
+    }
+}
+
+const HASH: u32 = Command::SCHEMA.hash();
+
+fn main() {
+    assert_eq!(
+        Command::SCHEMA.variants,
+        &[
+            VariantSchema {
+                name: "q",
+                aliases: &[],
+                ident: "Queue",
+                fields: &[
+                    FieldSchema {
+                        ident: Some("bottom"),
+                        ty: "f32",
+                        has_default: false,
+                        schema: None,
+                    },
+                    FieldSchema {
+                        ident: Some("speed"),
+                        ty: "f32",
+                        has_default: true,
+                        schema: None,
+                    },
+                ],
+                since: 0,
+            },
+            VariantSchema {
+                name: "pos",
+                aliases: &[],
+                ident: "Position",
+                fields: &[],
+                since: 0,
+            },
+        ]
+    );
+    assert_eq!(Command::SCHEMA.variant("pos").unwrap().ident, "Position");
+    assert!(Command::SCHEMA.variant("Position").is_none());
+    assert_eq!(
+        Command::SCHEMA.to_string(),
+        "q: Queue bottom=f32 speed=f32?\npos: Position\n"
+    );
+
+    assert_eq!(HASH, renamed::Cmd::SCHEMA.hash());
+    assert_ne!(HASH, changed::Command::SCHEMA.hash());
+}
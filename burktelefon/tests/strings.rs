@@ -0,0 +1,54 @@
+use burktelefon::frame::Binary;
+use burktelefon::text::ParseError;
+use burktelefon::Burk;
+
+#[derive(Burk, Debug, PartialEq)]
+#[burk(binary)]
+pub enum Command {
+    #[burk(name = "say")]
+    Say(String, #[burk(default = 1)] u32),
+    #[burk(name = "name")]
+    Name { name: String },
+}
+
+#[derive(Burk, Debug, PartialEq)]
+pub enum Borrowed<'a> {
+    #[burk(name = "log")]
+    Log(u8, &'a str),
+}
+
+fn main() {
+    let cmd: Command = r#"say "hello world" 3"#.parse().unwrap();
+    assert_eq!(cmd, Command::Say("hello world".into(), 3));
+    assert_eq!(cmd.to_string(), r#"say "hello world" 3"#);
+
+    let cmd = Command::Say("a \"quote\"\n\\".into(), 1);
+    assert_eq!(cmd.to_string(), r#"say "a \"quote\"\n\\" 1"#);
+    assert_eq!(cmd.to_string().parse::<Command>().unwrap(), cmd);
+
+    assert_eq!(
+        "say plain".parse::<Command>().unwrap(),
+        Command::Say("plain".into(), 1)
+    );
+    assert_eq!(
+        r#"name "unterminated"#.parse::<Command>(),
+        Err(ParseError::BadField(0))
+    );
+
+    let mut buf = [0; 64];
+    let cmd = Command::Name {
+        name: "robby".into(),
+    };
+    let n = cmd.to_frame(&mut buf).unwrap();
+    assert_eq!(Command::from_frame(&buf[..n]).unwrap(), cmd);
+
+    let line = String::from(r#"log 2 "waiting for USB""#);
+    let log = Borrowed::parse(&line).unwrap();
+    assert_eq!(log, Borrowed::Log(2, "waiting for USB"));
+    assert_eq!(log.to_string(), line);
+    // Escapes can't be resolved without allocating.
+    assert_eq!(
+        Borrowed::parse(r#"log 2 "a\"b""#),
+        Err(ParseError::BadField(1))
+    );
+}
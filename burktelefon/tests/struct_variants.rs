@@ -0,0 +1,52 @@
+use burktelefon::Burk;
+
+#[derive(Burk, Debug, PartialEq)]
+pub enum Command {
+    /// Queues a movement.
+    #[burk(name = "q")]
+    Queue {
+        bottom: f32,
+        top: f32,
+        sideways: f32,
+        /// Speed scaling.
+        #[burk(default = 1.0)]
+        speed: f32,
+    },
+    #[burk(name = "mv")]
+    Move(f32, #[burk(default = 0)] u32),
+    #[allow(dead_code)]
+    Other,
+}
+
+fn main() {
+    let cmd: Command = "q 90 45.5 0.1 0.5".parse().unwrap();
+    assert_eq!(
+        cmd,
+        Command::Queue {
+            bottom: 90.0,
+            top: 45.5,
+            sideways: 0.1,
+            speed: 0.5
+        }
+    );
+    assert_eq!(cmd.to_string(), "q 90 45.5 0.1 0.5");
+
+    let cmd: Command = "q 90 45.5 0.1".parse().unwrap();
+    assert_eq!(
+        cmd,
+        Command::Queue {
+            bottom: 90.0,
+            top: 45.5,
+            sideways: 0.1,
+            speed: 1.0
+        }
+    );
+    assert!("q 90 45.5".parse::<Command>().is_err());
+
+    assert_eq!("mv 1.5".parse::<Command>().unwrap(), Command::Move(1.5, 0));
+    assert_eq!(
+        "mv 1.5 3".parse::<Command>().unwrap(),
+        Command::Move(1.5, 3)
+    );
+    assert_eq!(Command::Other.to_string(), "OTHER");
+}
@@ -0,0 +1,9 @@
+use burktelefon::Burk;
+
+#[derive(Burk)]
+#[burk(binary)]
+pub enum Command<'a> {
+    Log(&'a str),
+}
+
+fn main() {}
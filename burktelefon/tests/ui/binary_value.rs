@@ -0,0 +1,9 @@
+use burktelefon::Burk;
+
+#[derive(Burk)]
+#[burk(binary = true)]
+pub enum Command {
+    Queue(f32, f32),
+}
+
+fn main() {}
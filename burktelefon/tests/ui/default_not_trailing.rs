@@ -0,0 +1,14 @@
+use burktelefon::Burk;
+
+#[derive(Burk)]
+pub enum Command {
+    #[burk(name = "q")]
+    Queue {
+        bottom: f32,
+        #[burk(default = 0.0)]
+        top: f32,
+        sideways: f32,
+    },
+}
+
+fn main() {}
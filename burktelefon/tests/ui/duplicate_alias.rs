@@ -0,0 +1,12 @@
+use burktelefon::Burk;
+
+#[derive(Burk)]
+#[burk(case_insensitive)]
+pub enum Command {
+    #[burk(name = "pos")]
+    Position,
+    #[burk(name = "qs", alias = "POS")]
+    QueueSize,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use burktelefon::Burk;
+
+#[derive(Burk)]
+pub enum Command {
+    #[burk(name = "POS")]
+    Position,
+    Pos,
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use burktelefon::Burk;
+
+#[derive(Burk)]
+pub enum Command {
+    #[burk(name = "pos")]
+    Position,
+    Queue(f32, f32),
+    /// Reads the sensors.
+    #[burk(name = "pos")]
+    Magnets,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use burktelefon::Burk;
+
+#[derive(Burk)]
+pub enum Command {
+    #[burk(name = "path")]
+    Path(Vec<u32>, u8),
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use burktelefon::Burk;
+
+#[derive(Burk)]
+pub struct Queue(f32, f32);
+
+fn main() {}
@@ -0,0 +1,9 @@
+use burktelefon::Burk;
+
+#[derive(Burk)]
+pub enum Command {
+    #[burk(reply = "Response::Position")]
+    Position,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use burktelefon::Burk;
+
+#[derive(Burk)]
+pub enum Command {
+    #[burk(since = "2")]
+    Stop,
+}
+
+fn main() {}
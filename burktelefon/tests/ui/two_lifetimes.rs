@@ -0,0 +1,8 @@
+use burktelefon::Burk;
+
+#[derive(Burk)]
+pub enum Command<'a, 'b> {
+    Log(&'a str, &'b str),
+}
+
+fn main() {}
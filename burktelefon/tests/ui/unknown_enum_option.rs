@@ -0,0 +1,9 @@
+use burktelefon::Burk;
+
+#[derive(Burk)]
+#[burk(binray)]
+pub enum Command {
+    Queue(f32, f32),
+}
+
+fn main() {}
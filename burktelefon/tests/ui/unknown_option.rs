@@ -0,0 +1,9 @@
+use burktelefon::Burk;
+
+#[derive(Burk)]
+pub enum Command {
+    #[burk(nmae = "q")]
+    Queue(f32, f32),
+}
+
+fn main() {}
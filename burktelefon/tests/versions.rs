@@ -0,0 +1,109 @@
+use burktelefon::Burk;
+
+#[derive(Burk, Debug, PartialEq)]
+#[burk(case_insensitive)]
+pub enum Command {
+    #[burk(name = "pos", alias = "position")]
+    Position,
+    #[burk(name = "mvs", alias = "sideways", alias = "side")]
+    MoveSideways(f32),
+    #[burk(name = "stop", since = 1)]
+    Stop,
+    #[burk(name = "pause", since = 2)]
+    Pause,
+}
+
+mod old {
+    use burktelefon::Burk;
+
+    #[derive(Burk)]
+    pub enum Command {
+        #[burk(name = "pos")]
+        Position,
+        #[burk(name = "mvs")]
+        MoveSideways(f32),
+        #[burk(name = "stop")]
+        Stop,
+    }
+}
+
+/// A binary enum with a field enum that got a variant in version 1.
+#[derive(Burk, Debug, PartialEq)]
+#[burk(binary)]
+pub enum Response {
+    #[burk(name = "err")]
+    Error(Code),
+}
+
+#[derive(Burk, Debug, PartialEq)]
+#[burk(binary)]
+pub enum Code {
+    #[burk(name = "parse")]
+    Parse,
+    #[burk(name = "full", since = 1)]
+    QueueFull,
+}
+
+mod old_response {
+    use burktelefon::Burk;
+
+    #[derive(Burk)]
+    #[burk(binary)]
+    pub enum Response {
+        #[burk(name = "err")]
+        Error(Code),
+    }
+
+    #[derive(Burk)]
+    #[burk(binary)]
+    pub enum Code {
+        #[burk(name = "parse")]
+        Parse,
+    }
+}
+
+#[derive(Burk, Debug, PartialEq)]
+pub enum CaseSensitive {
+    #[burk(name = "pos", alias = "position")]
+    Position,
+}
+
+fn main() {
+    for line in ["pos", "POS", "position", "Position"] {
+        assert_eq!(line.parse::<Command>().unwrap(), Command::Position);
+    }
+    assert_eq!(
+        "SIDE 1.5".parse::<Command>().unwrap(),
+        Command::MoveSideways(1.5)
+    );
+    // Values are always written with the command name.
+    assert_eq!(Command::MoveSideways(1.5).to_string(), "mvs 1.5");
+
+    assert!("position".parse::<CaseSensitive>().is_ok());
+    assert!("POS".parse::<CaseSensitive>().is_err());
+
+    assert_eq!(Command::SCHEMA.version(), 2);
+    assert_eq!(
+        Command::SCHEMA.variant("side").unwrap().ident,
+        "MoveSideways"
+    );
+    assert_eq!(Command::Stop.variant_schema().since, 1);
+    assert_eq!(Command::SCHEMA.hash_up_to(1), old::Command::SCHEMA.hash());
+    assert_ne!(Command::SCHEMA.hash_up_to(2), old::Command::SCHEMA.hash());
+    assert_eq!(Command::SCHEMA.hash_up_to(2), Command::SCHEMA.hash());
+    // The variants of field enums are part of the hash and the version.
+    assert_eq!(Response::SCHEMA.version(), 1);
+    assert_eq!(
+        Response::SCHEMA.hash_up_to(0),
+        old_response::Response::SCHEMA.hash()
+    );
+    assert_ne!(
+        Response::SCHEMA.hash(),
+        old_response::Response::SCHEMA.hash()
+    );
+
+    assert_eq!(
+        Command::SCHEMA.to_string(),
+        "pos|position: Position\nmvs|sideways|side: MoveSideways f32\nstop: Stop (since 1)\npause: Pause (since 2)\n"
+    );
+}
@@ -1,6 +1,9 @@
 MEMORY {
     BOOT2 : org = 0x10000000, len = 0x00000100
-    FLASH : org = 0x10000100, len = 0x001FFF00
+    /* The last 4 KiB sector holds the parameters, see arm/src/params.rs, the one before
+       it the saved position, see arm/src/position.rs, and the one before that the angle
+       sensor calibrations, see arm/src/calibration.rs. */
+    FLASH : org = 0x10000100, len = 0x001FCF00
     RAM   : org = 0x20000000, len = 0x00040000
 }
 
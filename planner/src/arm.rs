@@ -1,15 +1,32 @@
-use core::panic;
 use std::{
+    collections::VecDeque,
     f32::consts::PI,
+    fmt,
     io::{BufRead, BufReader, Error, ErrorKind, Write},
     ops,
-    time::Duration,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use burktelefon::{
+    frame::{Binary, MAX_FRAME_LEN},
+    request::{Link, RequestError},
+    Codec,
+};
 use glam::{Affine2, Vec2, Vec3};
-use robby_fischer::{Command, Response};
+use robby_fischer::{
+    Axis, Command, ErrorCode, Event, MotionState, Param, Response, Segment, MAX_UPLOAD_SEGMENTS,
+    SEQUENCE_IDS_SINCE,
+};
 
-use crate::{chess::Piece, termdev::TerminalDevice};
+use crate::{
+    chess::Piece,
+    termdev::{TerminalDevice, TerminalWriter},
+};
 
 #[cfg(feature = "vis")]
 use crate::visualizer::arm_vis::log_robot_state;
@@ -29,12 +46,111 @@ const TOP_ARM_LENGTH: f32 = 0.29;
 
 pub const CLAW_CHANGE_DELAY: u64 = 700;
 
+/// How long to wait for the firmware to acknowledge a command before sending it again.
+const ACK_TIMEOUT: Duration = Duration::from_millis(200);
+/// How many times a command is sent before giving up.
+const COMMAND_ATTEMPTS: u32 = 5;
+/// How long to wait for the answer to a request.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+/// How long a calibration or the queued movements may take.
+const EVENT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long [`Arm::calibrate_sensors`] may take, which sweeps both arms slowly.
+const SENSOR_CALIBRATION_TIMEOUT: Duration = Duration::from_secs(300);
+/// Events that nobody waits for are dropped after this many.
+const MAX_QUEUED_EVENTS: usize = 64;
+
+/// The errors of [`Arm`].
+#[derive(Debug)]
+pub enum ArmError {
+    Io(Error),
+    /// The firmware reported that a command failed, see [`Response::Error`].
+    Firmware(ErrorCode, u32),
+    /// The firmware answered a request with another response.
+    UnexpectedReply(Response),
+    /// The movements were stopped, see [`Arm::stop`].
+    Stopped,
+}
+
+impl ArmError {
+    /// True if nothing was received in time.
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, ArmError::Io(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut))
+    }
+}
+
+impl fmt::Display for ArmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArmError::Io(e) => write!(f, "{e}"),
+            ArmError::Firmware(code, detail) => match code {
+                ErrorCode::Parse => write!(f, "the firmware could not parse a command"),
+                ErrorCode::Sensor => write!(f, "the angle sensor at {detail:#x} could not be read"),
+                ErrorCode::Unreachable => write!(f, "a target position is unreachable"),
+                ErrorCode::QueueFull => write!(f, "the movement queue is full ({detail} moves)"),
+                ErrorCode::NotCalibrated => write!(f, "the arm is not calibrated"),
+                ErrorCode::BadValue => write!(f, "parameter {detail} can't have that value"),
+                ErrorCode::OutOfRange => {
+                    write!(f, "a target is outside of the limits of axis {detail}")
+                }
+                ErrorCode::Homing if *detail == 1 => {
+                    write!(f, "the sideways calibration timed out")
+                }
+                ErrorCode::Homing => write!(f, "the sideways limit switch was not found"),
+                ErrorCode::SensorFit => {
+                    write!(f, "the angle sensor at {detail:#x} could not be calibrated")
+                }
+                ErrorCode::StepSize => write!(f, "{detail} is not a supported step size"),
+                ErrorCode::Disabled => write!(f, "the driver of axis {detail} is off"),
+                ErrorCode::Busy => write!(f, "the arm has to stand still for that"),
+            },
+            ArmError::UnexpectedReply(response) => write!(f, "unexpected reply {response:?}"),
+            ArmError::Stopped => write!(f, "the movements were stopped"),
+        }
+    }
+}
+
+impl std::error::Error for ArmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArmError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for ArmError {
+    fn from(e: Error) -> Self {
+        ArmError::Io(e)
+    }
+}
+
+impl From<RequestError<ArmError, Response>> for ArmError {
+    fn from(e: RequestError<ArmError, Response>) -> Self {
+        match e {
+            RequestError::Link(e) => e,
+            RequestError::Unexpected(response) => ArmError::UnexpectedReply(response),
+        }
+    }
+}
+
 pub struct Arm {
     pub claw_pos: Vec3,
 
     pub translation_offset: Vec3,
-    writer: crate::termdev::TerminalWriter,
+    /// Shared with the [`StopHandle`]s, so that their commands don't end up in the
+    /// middle of one of ours.
+    writer: Arc<Mutex<crate::termdev::TerminalWriter>>,
     reader: BufReader<crate::termdev::TerminalReader>,
+    codec: Codec,
+    /// The protocol version of the firmware, see [`Arm::check_protocol`].
+    protocol_version: u32,
+    /// The sequence id of the next command, `None` if the firmware is too old for them.
+    next_seq: Option<u16>,
+    /// Responses that were received while waiting for an ack.
+    pending: VecDeque<Response>,
+    /// Events for [`Arm::wait_for_event`].
+    events: VecDeque<Event>,
+    subscribers: Vec<Sender<Event>>,
     pub grabbed_piece: Option<Piece>,
 }
 
@@ -47,30 +163,77 @@ impl Arm {
             claw_pos: Vec3::new(0.0, 0.0, 0.0),
             translation_offset: Vec3::new(0.0, 0.0, 0.0),
             reader,
-            writer,
+            writer: Arc::new(Mutex::new(writer)),
+            codec: Codec::Text,
+            protocol_version: Command::SCHEMA.version(),
+            next_seq: None,
+            pending: VecDeque::new(),
+            events: VecDeque::new(),
+            subscribers: Vec::new(),
             grabbed_piece: None,
         }
     }
 
-    pub fn calib(&mut self) -> std::io::Result<()> {
-        loop {
-            std::thread::sleep(Duration::from_millis(100));
-            self.send_command(Command::IsCalibrated)?;
-            let res = self.get_response();
-            if let Err(e) = &res {
-                if e.kind() == std::io::ErrorKind::WouldBlock {
-                    self.send_command(Command::IsCalibrated)?;
-                    continue;
+    /// Sets the codec used for commands. The firmware answers with the same codec.
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    /// Checks that the firmware speaks this protocol or an older version of it. Commands
+    /// that the firmware is too old for are refused by [`Arm::send_command`] afterwards.
+    pub fn check_protocol(&mut self) -> Result<(), ArmError> {
+        let latest = Command::SCHEMA.version().max(Response::SCHEMA.version());
+        for _ in 0..10 {
+            match Command::request_protocol(self) {
+                Ok((commands, responses)) => {
+                    let version = (0..=latest).rev().find(|&version| {
+                        Command::SCHEMA.hash_up_to(version) == commands
+                            && Response::SCHEMA.hash_up_to(version) == responses
+                    });
+                    let Some(version) = version else {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "firmware protocol {commands:08x}/{responses:08x} does not match {:08x}/{:08x}",
+                                Command::SCHEMA.hash(),
+                                Response::SCHEMA.hash(),
+                            ),
+                        )
+                        .into());
+                    };
+                    if version < latest {
+                        eprintln!("firmware uses protocol version {version} of {latest}, newer commands are disabled");
+                    }
+                    self.protocol_version = version;
+                    if version >= SEQUENCE_IDS_SINCE {
+                        // The firmware skips a command with the same id as the last
+                        // one, which could be left from an earlier run.
+                        let nanos = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |d| d.subsec_nanos());
+                        self.next_seq.get_or_insert(nanos as u16);
+                    }
+                    if self.events_supported() {
+                        self.send_command(Command::Events(true))?;
+                    }
+                    return Ok(());
                 }
+                // Old responses or no response yet.
+                Err(RequestError::Unexpected(_)) => {}
+                Err(RequestError::Link(e)) if e.is_timeout() => {}
+                Err(RequestError::Link(e)) => return Err(e),
             }
-            let response = res?;
-            dbg!(response);
-            if response != Response::IsCalibrated(true) {
-                self.send_command(Command::CalibrateSideways)?;
-            } else {
-                break;
-            }
-            self.send_command(Command::CalibrateArm)?;
+        }
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            "firmware does not answer the protocol query, it is probably too old",
+        )
+        .into())
+    }
+
+    pub fn calib(&mut self) -> Result<(), ArmError> {
+        if !Command::request_is_calibrated(self)? {
+            self.run_until(Command::CalibrateSideways, Event::SidewaysCalibrated)?;
         }
         println!("calibrated sideways!");
         self.sync_pos()?;
@@ -78,44 +241,284 @@ impl Arm {
         Ok(())
     }
 
-    pub fn calib_all_except_sideways(&mut self) -> std::io::Result<()> {
-        self.send_command(Command::CalibrateArm)?;
+    pub fn calib_all_except_sideways(&mut self) -> Result<(), ArmError> {
+        self.run_until(Command::CalibrateArm, Event::ArmCalibrated)?;
         let cur_y = self.claw_pos.y;
         self.move_claw_to(Vec3::new(0.0, cur_y, 0.15))?;
-        std::thread::sleep(Duration::from_millis(100));
-        self.send_command(Command::CalibrateArm)?;
+        self.wait_until_drained()?;
+        self.run_until(Command::CalibrateArm, Event::ArmCalibrated)?;
         self.move_claw_to(Vec3::new(0.0, cur_y, 0.15))?;
-        std::thread::sleep(Duration::from_millis(100));
-        self.send_command(Command::CalibrateArm)?;
-        std::thread::sleep(Duration::from_millis(100));
+        self.wait_until_drained()?;
+        self.run_until(Command::CalibrateArm, Event::ArmCalibrated)?;
         Ok(())
     }
 
-    pub fn sync_pos(&mut self) -> std::io::Result<()> {
+    /// Turns all stepper drivers off, so that the motors cool down between games, or on
+    /// again. The motors may have been turned while they were off, so turning them on
+    /// calibrates the arm again from the angle sensors, and sideways if it was lost.
+    pub fn set_drivers_enabled(&mut self, enabled: bool) -> Result<(), ArmError> {
+        for axis in [Axis::Bottom, Axis::Top, Axis::Sideways] {
+            self.send_command(Command::EnableDriver(axis, enabled))?;
+        }
+        if enabled {
+            self.calib()?;
+        }
+        Ok(())
+    }
+
+    /// Changes the microstepping of a stepper to `size` steps per full step.
+    pub fn set_step_size(&mut self, axis: Axis, size: u32) -> Result<(), ArmError> {
+        self.send_command(Command::SetStepSize(axis, size))
+    }
+
+    /// Sweeps the bottom and top arms between the angles in degrees to calibrate their
+    /// angle sensors, and returns the errors left in degrees. The arm is calibrated again
+    /// afterwards.
+    pub fn calibrate_sensors(
+        &mut self,
+        bottom: (f32, f32),
+        top: (f32, f32),
+    ) -> Result<(f32, f32), ArmError> {
+        self.forget_events();
+        self.send_command(Command::CalibrateSensors {
+            bottom_from: bottom.0,
+            bottom_to: bottom.1,
+            top_from: top.0,
+            top_to: top.1,
+        })?;
+        self.wait_for_event(SENSOR_CALIBRATION_TIMEOUT, |event| match event {
+            Event::SensorsCalibrated(bottom, top) => Some((bottom, top)),
+            _ => None,
+        })
+    }
+
+    /// Sends `command` and waits for the event that tells that it is done.
+    fn run_until(&mut self, command: Command, done: Event) -> Result<(), ArmError> {
+        self.forget_events();
+        self.send_command(command)?;
+        self.wait_for_event(EVENT_TIMEOUT, |event| (event == done).then_some(()))
+    }
+
+    /// Waits for the queued movements to be done.
+    fn wait_until_drained(&mut self) -> Result<(), ArmError> {
+        self.wait_for_motion(|event| (event == Event::QueueDrained).then_some(()))
+    }
+
+    /// Like [`Arm::wait_for_event`] for events of the queued movements. Fails if they
+    /// are stopped and doesn't time out while they are paused.
+    fn wait_for_motion<T>(&mut self, mut f: impl FnMut(Event) -> Option<T>) -> Result<T, ArmError> {
+        enum Motion<T> {
+            Done(T),
+            Stopped,
+            Paused(bool),
+        }
+        let mut paused = false;
         loop {
-            self.send_command(Command::Position)?;
-            let response = self.get_response()?;
-            if let Response::Position(a1, a2, sd) = response {
-                #[cfg(feature = "vis")]
-                log_robot_state(sd, a1, a2, self.grabbed_piece);
-
-                let cord2d = Arm::position_from_angles(a1, a2);
-                self.claw_pos = Vec3::new(cord2d[0], sd, cord2d[1]) + self.translation_offset;
-                break;
+            let motion = self.wait_for_event(EVENT_TIMEOUT, |event| match event {
+                Event::Stopped => Some(Motion::Stopped),
+                Event::Paused => Some(Motion::Paused(true)),
+                Event::Resumed => Some(Motion::Paused(false)),
+                event => f(event).map(Motion::Done),
+            });
+            match motion {
+                Ok(Motion::Done(value)) => return Ok(value),
+                Ok(Motion::Stopped) => return Err(ArmError::Stopped),
+                Ok(Motion::Paused(is_paused)) => paused = is_paused,
+                Err(e) if paused && e.is_timeout() => {}
+                Err(e) => return Err(e),
             }
-            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Halts the arm where it is and flushes the queue. Movements that are waited for
+    /// fail with [`ArmError::Stopped`].
+    pub fn stop(&mut self) -> Result<(), ArmError> {
+        self.send_command(Command::Stop)
+    }
+
+    /// Halts the arm where it is but keeps the queue, see [`Arm::resume`].
+    pub fn pause(&mut self) -> Result<(), ArmError> {
+        self.send_command(Command::Pause)
+    }
+
+    pub fn resume(&mut self) -> Result<(), ArmError> {
+        self.send_command(Command::Resume)
+    }
+
+    /// Flushes the queue, the current movement is finished.
+    pub fn clear_queue(&mut self) -> Result<(), ArmError> {
+        self.send_command(Command::ClearQueue)
+    }
+
+    /// Returns what the queued movements are doing and how many are left.
+    pub fn motion_status(&mut self) -> Result<(MotionState, u32), ArmError> {
+        Ok(Command::request_motion_status(self)?)
+    }
+
+    /// Changes a firmware parameter, which is kept across restarts.
+    pub fn set_param(&mut self, param: Param, value: f32) -> Result<(), ArmError> {
+        self.check_param(param)?;
+        self.send_command(Command::SetParam(param, value))
+    }
+
+    pub fn get_param(&mut self, param: Param) -> Result<f32, ArmError> {
+        self.check_param(param)?;
+        let (_param, value) = Command::request_get_param(self, param)?;
+        Ok(value)
+    }
+
+    /// Fails if the firmware is too old to know the parameter, like
+    /// [`Arm::send_command`] does for commands.
+    fn check_param(&self, param: Param) -> Result<(), ArmError> {
+        let since = param.variant_schema().since;
+        if since > self.protocol_version {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("the firmware is too old for {param:?}, it needs protocol version {since}"),
+            )
+            .into());
         }
         Ok(())
     }
 
-    pub fn move_claw(&mut self, change: Vec3) -> std::io::Result<()> {
+    /// Restores the parameters the firmware was built with.
+    pub fn reset_params(&mut self) -> Result<(), ArmError> {
+        self.send_command(Command::ResetParams)
+    }
+
+    /// Returns a handle that can stop the arm from another thread, while this one is
+    /// waiting for the arm.
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle {
+            writer: Arc::clone(&self.writer),
+            codec: self.codec,
+        }
+    }
+
+    fn events_supported(&self) -> bool {
+        Command::Events(true).variant_schema().since <= self.protocol_version
+    }
+
+    /// Returns a receiver of the events from now on. They are delivered while reading
+    /// from the firmware, [`Arm::poll_events`] reads without waiting for anything else.
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Reads everything the firmware has sent so far.
+    pub fn poll_events(&mut self) -> Result<(), ArmError> {
+        loop {
+            match self.read_response() {
+                // Delivered by `read_response`.
+                Ok(Response::Event(_)) => {}
+                Ok(Response::Error(code, detail)) => return Err(ArmError::Firmware(code, detail)),
+                Ok(response) => self.pending.push_back(response),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Waits for the first event that `f` returns something for and removes it, the
+    /// others are kept for later.
+    pub fn wait_for_event<T>(
+        &mut self,
+        timeout: Duration,
+        mut f: impl FnMut(Event) -> Option<T>,
+    ) -> Result<T, ArmError> {
+        if !self.events_supported() {
+            return Err(
+                Error::new(ErrorKind::Unsupported, "the firmware is too old for events").into(),
+            );
+        }
+        let deadline = Instant::now() + timeout;
+        let mut skipped = VecDeque::new();
+        let result = 'wait: loop {
+            while let Some(event) = self.events.pop_front() {
+                match f(event) {
+                    Some(value) => break 'wait Ok(value),
+                    None => skipped.push_back(event),
+                }
+            }
+            if Instant::now() >= deadline {
+                break Err(
+                    Error::new(ErrorKind::TimedOut, "timed out waiting for an event").into(),
+                );
+            }
+            match self.read_response() {
+                Ok(Response::Event(_)) => {}
+                Ok(Response::Error(code, detail)) => break Err(ArmError::Firmware(code, detail)),
+                Ok(response) => self.pending.push_back(response),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => break Err(e.into()),
+            }
+        };
+        // Put back in front of the ones that came in after them.
+        while let Some(event) = skipped.pop_back() {
+            self.events.push_front(event);
+        }
+        self.trim_events(MAX_QUEUED_EVENTS);
+        result
+    }
+
+    /// Drops the oldest events until there are at most `len` left, button presses last.
+    fn trim_events(&mut self, len: usize) {
+        while self.events.len() > len {
+            let oldest = self
+                .events
+                .iter()
+                .position(|&event| event != Event::ChessButton);
+            self.events.remove(oldest.unwrap_or(0));
+        }
+    }
+
+    /// Drops the events that came in so far, except for the button presses that nobody
+    /// has waited for yet.
+    fn forget_events(&mut self) {
+        self.events.retain(|&event| event == Event::ChessButton);
+    }
+
+    fn dispatch(&mut self, event: Event) {
+        match event {
+            Event::Stall(bottom, top, corrected) | Event::Drift(bottom, top, corrected) => {
+                eprintln!(
+                    "the arm is off by {bottom:.1}° at the bottom and {top:.1}° at the top{}",
+                    if corrected { ", corrected" } else { "" }
+                );
+            }
+            _ => {}
+        }
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event).is_ok());
+        self.trim_events(MAX_QUEUED_EVENTS - 1);
+        self.events.push_back(event);
+    }
+
+    pub fn sync_pos(&mut self) -> Result<(), ArmError> {
+        let (a1, a2, sd) = Command::request_position(self)?;
+        #[cfg(feature = "vis")]
+        log_robot_state(sd, a1, a2, self.grabbed_piece);
+
+        let cord2d = Arm::position_from_angles(a1, a2);
+        self.claw_pos = Vec3::new(cord2d[0], sd, cord2d[1]) + self.translation_offset;
+        Ok(())
+    }
+
+    pub fn move_claw(&mut self, change: Vec3) -> Result<(), ArmError> {
         self.move_claw_to(self.claw_pos + change)
     }
 
-    pub fn move_claw_to(&mut self, position: Vec3) -> std::io::Result<()> {
+    pub fn move_claw_to(&mut self, position: Vec3) -> Result<(), ArmError> {
         self.claw_pos = position;
         let (a1, a2, sd) = dbg!(self.angles(position));
-        self.send_command(Command::Queue(a1 as f32, a2 as f32, sd as f32, 1.0))?;
+        self.send_command(Command::Queue {
+            bottom: a1,
+            top: a2,
+            sideways: sd,
+            speed: 1.0,
+        })?;
         Ok(())
     }
 
@@ -126,15 +529,104 @@ impl Arm {
         (a1, a2, (pos - self.translation_offset).y)
     }
 
-    pub fn send_command(&mut self, command: Command) -> std::io::Result<()> {
-        let mut buf: Vec<_> = command.to_string().bytes().collect();
-        buf.push(b'\n');
-        self.writer.write_all(&buf)?;
-        self.writer.flush()?;
+    pub fn send_command(&mut self, command: Command) -> Result<(), ArmError> {
+        let since = command.variant_schema().since;
+        if since > self.protocol_version {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "the firmware is too old for {command:?}, it needs protocol version {since}"
+                ),
+            )
+            .into());
+        }
+        let Some(seq) = self.next_seq else {
+            return Ok(self.write_command(None, &command)?);
+        };
+        self.next_seq = Some(seq.wrapping_add(1));
+        for _ in 0..COMMAND_ATTEMPTS {
+            self.write_command(Some(seq), &command)?;
+            if self.wait_for_ack(seq)? {
+                return Ok(());
+            }
+        }
+        Err(Error::new(
+            ErrorKind::TimedOut,
+            format!("the firmware did not acknowledge {command:?}"),
+        )
+        .into())
+    }
+
+    fn write_command(&mut self, seq: Option<u16>, command: &Command) -> std::io::Result<()> {
+        let buf = encode_command(self.codec, seq, command)?;
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&buf)?;
+        writer.flush()?;
         Ok(())
     }
 
-    pub fn get_response(&mut self) -> std::io::Result<Response> {
+    /// Returns false if the command was not acknowledged in time or was garbled on the
+    /// way. Other responses are kept for [`Arm::get_response`].
+    fn wait_for_ack(&mut self, seq: u16) -> Result<bool, ArmError> {
+        let deadline = Instant::now() + ACK_TIMEOUT;
+        while Instant::now() < deadline {
+            match self.read_response() {
+                Ok(Response::Ack(acked)) if acked == seq => return Ok(true),
+                Ok(Response::Nack(nacked)) if nacked == seq => return Ok(false),
+                // Late answers to earlier attempts.
+                Ok(Response::Ack(_) | Response::Nack(_)) => {}
+                // The command was garbled so much that its sequence id was lost.
+                Ok(Response::Error(ErrorCode::Parse, _)) => return Ok(false),
+                // Sent before the ack, so it is about this command.
+                Ok(Response::Error(code, detail)) => return Err(ArmError::Firmware(code, detail)),
+                Ok(response) => self.pending.push_back(response),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                // A garbled response, the firmware sends the ack again on the next attempt.
+                Err(e) if e.kind() == ErrorKind::InvalidData => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(false)
+    }
+
+    pub fn get_response(&mut self) -> Result<Response, ArmError> {
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        loop {
+            let response = match self.pending.pop_front() {
+                Some(response) => response,
+                None => match self.read_response() {
+                    Ok(response) => response,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock && Instant::now() < deadline => {
+                        continue
+                    }
+                    Err(e) => return Err(e.into()),
+                },
+            };
+            match response {
+                // Late answers to commands that were sent again, and events that have
+                // been delivered already.
+                Response::Ack(_) | Response::Nack(_) | Response::Event(_) => {}
+                Response::Error(code, detail) => return Err(ArmError::Firmware(code, detail)),
+                response => return Ok(response),
+            }
+        }
+    }
+
+    /// Reads the next response and delivers it if it is an event.
+    fn read_response(&mut self) -> std::io::Result<Response> {
+        let response = match self.codec {
+            Codec::Text => self.get_text_response()?,
+            Codec::Binary => self.get_binary_response()?,
+        };
+        if let Response::Event(event) = response {
+            self.dispatch(event);
+        }
+        Ok(response)
+    }
+
+    fn get_text_response(&mut self) -> std::io::Result<Response> {
         let mut buf = Vec::new();
         let res = self.reader.read_until(b'\n', &mut buf);
         match res {
@@ -148,12 +640,25 @@ impl Arm {
                 }
                 trimmed
                     .parse()
-                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{e}: {trimmed:?}")))
             }
             Err(e) => Err(e.into()),
         }
     }
 
+    fn get_binary_response(&mut self) -> std::io::Result<Response> {
+        let mut buf = Vec::new();
+        // Skips the zero byte in front of the frame.
+        while buf.is_empty() || buf == [0] {
+            buf.clear();
+            if self.reader.read_until(0, &mut buf)? == 0 {
+                let e = Error::new(std::io::ErrorKind::WouldBlock, "reading timed out");
+                return Err(e);
+            }
+        }
+        Response::from_frame(&buf).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+    }
+
     pub fn arm_2d_angles(position: Vec3) -> (f32, f32) {
         let theta = (position.z).atan2(position.x);
         let d = Vec2::new(position.x, position.z).length();
@@ -176,7 +681,7 @@ impl Arm {
         rot1.transform_point2(bottom_arm + rot2.transform_point2(top_arm))
     }
 
-    pub fn smooth_move_z(&mut self, z: f32) -> std::io::Result<()> {
+    pub fn smooth_move_z(&mut self, z: f32) -> Result<(), ArmError> {
         let mut pos = self.claw_pos;
         pos.z = z;
         self.practical_smooth_move_claw_to(pos)
@@ -192,43 +697,124 @@ impl Arm {
         pos
     }
 
-    pub fn practical_smooth_move_claw_to(&mut self, pos: Vec3) -> std::io::Result<()> {
+    pub fn practical_smooth_move_claw_to(&mut self, pos: Vec3) -> Result<(), ArmError> {
         let target_pos = Self::practical_real_world_coordinate(pos);
         // let target_pos = pos;
         const N_POINTS_CM: f32 = 3.0;
         let npoints = (self.claw_pos - target_pos).length() * 100.0 * N_POINTS_CM;
-        for chunk in linspace(self.claw_pos, target_pos, npoints as u32)
-            .map(|e| (e, Arm::speed_factor(self.claw_pos, e, target_pos)))
-            .collect::<Vec<_>>()
-            .chunks(20)
-        {
-            for &(cur_point, scale) in chunk {
-                // dbg!(p);
+        let segments: Vec<_> = linspace(self.claw_pos, target_pos, npoints as u32)
+            .map(|cur_point| {
                 let (a1, a2, sd) = self.angles(cur_point);
-                // dbg!(a1, a2, sd);
-                // dbg!(self.claw_pos);
-                self.send_command(Command::Queue(
-                    a1 as f32,
-                    a2 as f32,
-                    sd as f32,
-                    scale as f32,
-                ))?;
+                Segment {
+                    bottom: a1,
+                    top: a2,
+                    sideways: sd,
+                    speed: Arm::speed_factor(self.claw_pos, cur_point, target_pos),
+                }
+            })
+            .collect();
+        if Command::Credit.variant_schema().since <= self.protocol_version {
+            self.stream(&segments)?;
+        } else if Command::QueueMany(Vec::new()).variant_schema().since <= self.protocol_version {
+            self.upload(&segments)?;
+        } else {
+            for chunk in segments.chunks(20) {
+                for &segment in chunk {
+                    self.send_command(Command::Queue {
+                        bottom: segment.bottom,
+                        top: segment.top,
+                        sideways: segment.sideways,
+                        speed: segment.speed,
+                    })?;
+                }
+                self.forget_events();
+                let mut queued = self.queue_size()?;
+                while queued >= 15 {
+                    queued = self.wait_for_segment_started()?;
+                }
             }
-            while self.queue_size()? >= 15 {
-                std::thread::sleep(Duration::from_millis(100));
-                self.sync_pos().unwrap();
+        }
+        self.wait_until_drained()?;
+        self.sync_pos()?;
+        self.claw_pos = target_pos;
+        Ok(())
+    }
+
+    /// Queues the segments [`MAX_UPLOAD_SEGMENTS`] at a time, waiting for room whenever
+    /// the queue is full.
+    fn upload(&mut self, mut segments: &[Segment]) -> Result<(), ArmError> {
+        while !segments.is_empty() {
+            let n = segments.len().min(MAX_UPLOAD_SEGMENTS);
+            self.forget_events();
+            let (accepted, _queued) = Command::request_queue_many(self, segments[..n].to_vec())?;
+            if accepted == 0 {
+                self.wait_for_segment_started()?;
             }
+            segments = &segments[accepted as usize..];
         }
-        while self.queue_size()? != 0 {
-            std::thread::sleep(Duration::from_millis(100));
-            self.sync_pos().unwrap();
+        self.forget_drained();
+        Ok(())
+    }
+
+    /// Queues the segments as fast as the firmware hands out room for them, see
+    /// [`Command::Credit`]. Unlike [`Arm::upload`] this never asks how full the queue is.
+    fn stream(&mut self, mut segments: &[Segment]) -> Result<(), ArmError> {
+        // Left over from the last movements, the credit asked for below replaces them.
+        self.forget_events();
+        let mut credit = Command::request_credit(self)?;
+        while !segments.is_empty() {
+            credit += self.take_credit();
+            if credit == 0 {
+                credit = self.wait_for_motion(|event| match event {
+                    Event::Credit(n) => Some(n),
+                    _ => None,
+                })?;
+                continue;
+            }
+            let n = segments.len().min(MAX_UPLOAD_SEGMENTS).min(credit as usize);
+            let (accepted, _queued) = Command::request_queue_many(self, segments[..n].to_vec())?;
+            // The firmware only turns segments away if the credit is out of step, the
+            // next credit event is up to date again.
+            credit = if accepted as usize == n {
+                credit - accepted
+            } else {
+                0
+            };
+            segments = &segments[accepted as usize..];
         }
-        std::thread::sleep(Duration::from_millis(300));
-        self.sync_pos().unwrap();
-        self.claw_pos = target_pos;
+        self.forget_drained();
         Ok(())
     }
 
+    /// Drops the [`Event::QueueDrained`]s that came in while queueing. They come in
+    /// before the answer to the last request, so the queue ran empty before the last
+    /// segments were in it.
+    fn forget_drained(&mut self) {
+        self.events.retain(|&event| event != Event::QueueDrained);
+    }
+
+    /// Removes the [`Event::Credit`]s that have come in and returns their sum.
+    fn take_credit(&mut self) -> u32 {
+        let mut credit = 0;
+        self.events.retain(|event| match event {
+            Event::Credit(n) => {
+                credit += n;
+                false
+            }
+            _ => true,
+        });
+        credit
+    }
+
+    /// Waits for the next queued movement to start and returns the number of movements
+    /// left in the queue.
+    fn wait_for_segment_started(&mut self) -> Result<u32, ArmError> {
+        self.wait_for_motion(|event| match event {
+            Event::SegmentStarted(queued) => Some(queued),
+            _ => None,
+        })
+    }
+
     pub fn speed_factor(start: Vec3, cur: Vec3, dst: Vec3) -> f32 {
         let min_dist = (start - cur).length().min((cur - dst).length());
         if min_dist < 0.05 {
@@ -238,38 +824,19 @@ impl Arm {
         }
     }
 
-    fn queue_size(&mut self) -> std::io::Result<u32> {
-        loop {
-            self.send_command(Command::QueueSize)?;
-            std::thread::sleep(Duration::from_millis(10));
-            let res = self.get_response();
-            match res {
-                Ok(response) => {
-                    if let Response::QueueSize(in_queue, _max) = response {
-                        return Ok(in_queue);
-                    } else {
-                        panic!("expected QueueSize, got '{:?}'", response);
-                    }
-                }
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::WouldBlock {
-                        continue;
-                    } else {
-                        return Err(e);
-                    }
-                }
-            }
-        }
+    fn queue_size(&mut self) -> Result<u32, ArmError> {
+        let (in_queue, _max) = Command::request_queue_size(self)?;
+        Ok(in_queue)
     }
 
-    pub fn grip(&mut self) -> std::io::Result<()> {
+    pub fn grip(&mut self) -> Result<(), ArmError> {
         std::thread::sleep(Duration::from_millis(200));
         self.send_command(Command::Grip)?;
         std::thread::sleep(Duration::from_millis(CLAW_CHANGE_DELAY));
         Ok(())
     }
 
-    pub fn release(&mut self) -> std::io::Result<()> {
+    pub fn release(&mut self) -> Result<(), ArmError> {
         std::thread::sleep(Duration::from_millis(200));
         self.send_command(Command::Release)?;
         std::thread::sleep(Duration::from_millis(CLAW_CHANGE_DELAY));
@@ -277,6 +844,90 @@ impl Arm {
     }
 }
 
+fn encode_command(codec: Codec, seq: Option<u16>, command: &Command) -> std::io::Result<Vec<u8>> {
+    match codec {
+        Codec::Text => {
+            let line = match seq {
+                Some(seq) => format!("@{seq} {command}\n"),
+                None => format!("{command}\n"),
+            };
+            Ok(line.into_bytes())
+        }
+        Codec::Binary => {
+            let mut buf = vec![0; MAX_FRAME_LEN];
+            let len = match seq {
+                Some(seq) => command.to_frame_seq(seq, &mut buf),
+                None => command.to_frame(&mut buf),
+            }
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+            buf.truncate(len);
+            Ok(buf)
+        }
+    }
+}
+
+/// Sends [`Command::Stop`], [`Command::Pause`] and [`Command::Resume`] without waiting
+/// for the [`Arm`] it was created from. See [`Arm::stop_handle`].
+pub struct StopHandle {
+    writer: Arc<Mutex<TerminalWriter>>,
+    codec: Codec,
+}
+
+impl StopHandle {
+    pub fn stop(&mut self) -> std::io::Result<()> {
+        self.send(Command::Stop)
+    }
+
+    pub fn pause(&mut self) -> std::io::Result<()> {
+        self.send(Command::Pause)
+    }
+
+    pub fn resume(&mut self) -> std::io::Result<()> {
+        self.send(Command::Resume)
+    }
+
+    /// Sent without a sequence id, since the acks are read by the [`Arm`]. Not flushed,
+    /// that would drop what the [`Arm`] hasn't read yet.
+    fn send(&mut self, command: Command) -> std::io::Result<()> {
+        let buf = encode_command(self.codec, None, &command)?;
+        self.writer.lock().unwrap().write_all(&buf)
+    }
+
+    /// Reads commands from stdin on a thread of its own: an empty line stops the arm,
+    /// `p` pauses it and `r` resumes it.
+    pub fn spawn_keyboard(mut self) -> JoinHandle<()> {
+        println!("press enter to stop the arm, p and enter to pause it, r and enter to resume");
+        thread::spawn(move || {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                let result = match line.trim() {
+                    "" => self.stop(),
+                    "p" => self.pause(),
+                    "r" => self.resume(),
+                    _ => continue,
+                };
+                if let Err(e) = result {
+                    eprintln!("could not reach the arm: {e}");
+                }
+            }
+        })
+    }
+}
+
+impl Link<Command, Response> for Arm {
+    type Error = ArmError;
+
+    fn send(&mut self, command: &Command) -> Result<(), ArmError> {
+        self.send_command(command.clone())
+    }
+
+    fn receive(&mut self) -> Result<Response, ArmError> {
+        self.get_response()
+    }
+}
+
 fn linspace<T>(start: T, end: T, n: u32) -> impl Iterator<Item = T>
 where
     T: Copy
@@ -65,6 +65,7 @@ fn run(_terminal: &mut Terminal<impl Backend>) -> anyhow::Result<Vec3> {
     td.configure(BaudRate::B115200)?;
     td.set_timeout(1)?;
     let mut arm = Arm::new(td);
+    arm.check_protocol()?;
 
     println!("checking calib...");
     arm.calib()?;
@@ -76,7 +77,12 @@ fn run(_terminal: &mut Terminal<impl Backend>) -> anyhow::Result<Vec3> {
     let mut theta2 = 90.0;
 
     println!("Getting currenst position...");
-    arm.send_command(Command::Queue(90.0, 90.0, 0.0, 1.0))?;
+    arm.send_command(Command::Queue {
+        bottom: 90.0,
+        top: 90.0,
+        sideways: 0.0,
+        speed: 1.0,
+    })?;
 
     let mut changed = true;
     loop {
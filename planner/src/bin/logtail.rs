@@ -0,0 +1,60 @@
+//! Prints the firmware log next to the protocol traffic. Lines typed in are sent to the
+//! arm as text commands, the lines it answers with are printed after `arm` and the
+//! lines from its log port after `log`.
+//!
+//! The serial ports can be given as arguments, the command port first.
+
+use std::io::{BufRead, BufReader, Write};
+use std::thread::{self, JoinHandle};
+
+use nix::sys::termios::BaudRate;
+use planner::termdev::{TerminalDevice, TerminalReader};
+
+const COMMAND_PORT: &str = "/dev/serial/by-id/usb-alebe_herla_robby_fischer_1972-if00";
+const LOG_PORT: &str = "/dev/serial/by-id/usb-alebe_herla_robby_fischer_1972-if02";
+
+fn open(path: &str) -> anyhow::Result<TerminalDevice> {
+    let mut td = TerminalDevice::new(path)?;
+    td.configure(BaudRate::B115200)?;
+    td.set_timeout(1)?;
+    Ok(td)
+}
+
+/// Prints the lines read from `reader` with `prefix` in front, until reading fails.
+fn tail(reader: TerminalReader, prefix: &'static str) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        loop {
+            match reader.read_until(b'\n', &mut line) {
+                // Timed out in the middle of a line, or before it.
+                Ok(_) if !line.ends_with(b"\n") => {}
+                Ok(_) => {
+                    println!("{prefix} {}", String::from_utf8_lossy(&line).trim_end());
+                    line.clear();
+                }
+                Err(e) => {
+                    eprintln!("{prefix} {e}");
+                    return;
+                }
+            }
+        }
+    })
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let command_port = args.next().unwrap_or_else(|| COMMAND_PORT.to_string());
+    let log_port = args.next().unwrap_or_else(|| LOG_PORT.to_string());
+
+    let (log, _) = open(&log_port)?.split();
+    tail(log, "log");
+    let (responses, mut commands) = open(&command_port)?.split();
+    tail(responses, "arm");
+
+    for line in std::io::stdin().lines() {
+        // Not flushed, that would drop what hasn't been read yet.
+        commands.write_all(format!("{}\n", line?.trim()).as_bytes())?;
+    }
+    Ok(())
+}
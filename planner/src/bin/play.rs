@@ -6,7 +6,7 @@ use glam::Vec3;
 use nix::sys::termios::BaudRate;
 
 use planner::{
-    arm::Arm,
+    arm::{Arm, ArmError},
     board::chess_pos_to_board,
     chess::{Color, Square},
     moves::PieceMove,
@@ -19,7 +19,7 @@ use planner::visualizer::{arm_vis::init_arm_vis, BOARD_VISUALIZER};
 #[cfg(feature = "vis")]
 use rerun::RecordingStream;
 
-use robby_fischer::{Command, Response};
+use robby_fischer::{ErrorCode, Event};
 use shakmaty::{uci::Uci, Chess, Position};
 use std::{sync::mpsc::sync_channel, time::Duration};
 
@@ -60,11 +60,40 @@ fn chess_move_to_move(mv: shakmaty::Move) -> Option<PieceMove> {
     }
 }
 
+/// Recalibrates if the firmware says that it is needed and waits for the chess button
+/// after the arm was stopped, errors that the arm can't recover from are returned.
+fn handle_arm_error(arm: &mut Arm, e: ArmError) -> anyhow::Result<()> {
+    println!("{e}");
+    match e {
+        ArmError::Firmware(ErrorCode::NotCalibrated, _) => arm.calib()?,
+        ArmError::Firmware(ErrorCode::Disabled, _) => arm.set_drivers_enabled(true)?,
+        // A loose sensor cable.
+        ArmError::Firmware(ErrorCode::Sensor, _) => return Err(e.into()),
+        ArmError::Stopped => {
+            arm.sync_pos()?;
+            println!("press the chess button to go on");
+            loop {
+                match arm.wait_for_event(Duration::from_secs(1), |event| {
+                    (event == Event::ChessButton).then_some(())
+                }) {
+                    Ok(()) => break,
+                    Err(e) if e.is_timeout() => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     let mut td = TerminalDevice::new("/dev/serial/by-id/usb-alebe_herla_robby_fischer_1972-if00")?;
     td.configure(BaudRate::B115200)?;
     td.set_timeout(1)?;
     let mut arm = Arm::new(td);
+    arm.check_protocol()?;
+    arm.stop_handle().spawn_keyboard();
 
     // arm.translation_offset = Vec3::new(-0.1383520286271571, -0.015, -0.015553090130407);
     arm.translation_offset =
@@ -145,14 +174,15 @@ fn main() -> anyhow::Result<()> {
 
     let mut moves_since_cailbration = 0;
     loop {
-        std::thread::sleep(Duration::from_millis(10));
-        arm.send_command(Command::ChessButton).unwrap();
-        if let Ok(Response::ChessButtonStatus(pressed)) = arm.get_response() {
-            if !pressed {
+        match arm.wait_for_event(Duration::from_secs(1), |event| {
+            (event == Event::ChessButton).then_some(())
+        }) {
+            Ok(()) => {}
+            Err(e) if e.is_timeout() => continue,
+            Err(e) => {
+                handle_arm_error(&mut arm, e)?;
                 continue;
             }
-        } else {
-            continue;
         }
 
         let Some(pieces) = vision_recv.recv().unwrap() else {
@@ -193,7 +223,7 @@ fn main() -> anyhow::Result<()> {
         BOARD_VISUALIZER.log_piece_positions(&new_board);
 
         if let Err(e) = arm.smooth_move_z(0.2) {
-            dbg!(e);
+            handle_arm_error(&mut arm, e)?;
             continue;
         }
         board = new_board;
@@ -203,7 +233,7 @@ fn main() -> anyhow::Result<()> {
         let target = chess_pos_to_board(chess_board.clone()).unwrap();
         for (src, dst) in board.diff(&target) {
             if let Err(e) = board.move_piece(&mut arm, src, dst) {
-                dbg!(e);
+                handle_arm_error(&mut arm, e)?;
                 continue;
             }
         }
@@ -222,7 +252,7 @@ fn main() -> anyhow::Result<()> {
         let target = chess_pos_to_board(chess_board.clone()).unwrap();
         for (src, dst) in board.diff(&target) {
             if let Err(e) = board.move_piece(&mut arm, src, dst) {
-                dbg!(e);
+                handle_arm_error(&mut arm, e)?;
                 continue;
             }
         }
@@ -232,17 +262,17 @@ fn main() -> anyhow::Result<()> {
         BOARD_VISUALIZER.log_piece_positions(&board);
 
         if let Err(e) = arm.practical_smooth_move_claw_to(Vec3::new(0.1, 0.48, 0.15)) {
-            dbg!(e);
+            handle_arm_error(&mut arm, e)?;
             continue;
         }
         if moves_since_cailbration >= 10 {
             if let Err(e) = arm.calib_all_except_sideways() {
-                dbg!(e);
+                handle_arm_error(&mut arm, e)?;
                 continue;
             }
             loop {
                 if let Err(e) = arm.practical_smooth_move_claw_to(Vec3::new(0.1, 0.48, 0.15)) {
-                    dbg!(e);
+                    handle_arm_error(&mut arm, e)?;
                     std::thread::sleep(Duration::from_millis(100));
                     continue;
                 }
@@ -13,6 +13,7 @@ fn main() -> anyhow::Result<()> {
     td.configure(BaudRate::B115200)?;
     td.set_timeout(1)?;
     let mut arm = Arm::new(td);
+    arm.check_protocol()?;
     arm.translation_offset =
         -Vec3::new(0.1411907894023803, 0.07200000000000005, 0.0243057524245006);
     arm.calib()?;
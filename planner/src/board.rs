@@ -1,5 +1,5 @@
 use crate::{
-    arm::Arm,
+    arm::{Arm, ArmError},
     chess::{Color, Piece, Role, Square},
     moves::{
         bishop_moves, king_moves, knight_moves, pawn_moves, queen_moves, rook_moves, PieceMove,
@@ -343,7 +343,12 @@ impl Board {
         Ok(())
     }
 
-    pub fn move_piece(&mut self, arm: &mut Arm, start: Square, end: Square) -> std::io::Result<()> {
+    pub fn move_piece(
+        &mut self,
+        arm: &mut Arm,
+        start: Square,
+        end: Square,
+    ) -> Result<(), ArmError> {
         assert!(start.file < 14);
         assert!(start.rank < 8);
         assert!(end.file < 14);
@@ -51,9 +51,17 @@ impl TerminalDevice {
         self.termios.output_flags &=
             !(OutputFlags::ONLCR | OutputFlags::ONOCR | OutputFlags::OCRNL);
         self.termios.output_flags |= OutputFlags::ONLRET;
-        self.termios.local_flags &= !(LocalFlags::ECHO | LocalFlags::ICANON);
-        self.termios.input_flags |= InputFlags::IGNCR;
-        self.termios.input_flags &= !(InputFlags::INPCK | InputFlags::ISTRIP);
+        // Binary frames must be received untouched, so no input processing at all, not
+        // even the control characters that would be taken as signals.
+        self.termios.local_flags &=
+            !(LocalFlags::ECHO | LocalFlags::ICANON | LocalFlags::ISIG | LocalFlags::IEXTEN);
+        self.termios.input_flags &= !(InputFlags::INPCK
+            | InputFlags::ISTRIP
+            | InputFlags::IGNCR
+            | InputFlags::ICRNL
+            | InputFlags::INLCR
+            | InputFlags::IXON
+            | InputFlags::IXOFF);
 
         self.termios.control_chars[SpecialCharacterIndices::VMIN as usize] = 0;
         self.termios.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
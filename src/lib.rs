@@ -1,32 +1,353 @@
 #![no_std]
-use burktelefon::Burk;
 extern crate alloc;
 
+use alloc::vec::Vec;
+use burktelefon::frame::{Field, FrameError, Reader, Writer};
+use burktelefon::Burk;
+use core::{fmt, str::FromStr};
+
+/// The protocol version that added sequence ids to commands, which the firmware answers
+/// with [`Response::Ack`] or [`Response::Nack`]. See [`burktelefon::text::split_seq`] and
+/// [`burktelefon::frame::frame_seq`].
+pub const SEQUENCE_IDS_SINCE: u32 = 1;
+
 #[derive(Burk, Clone, Copy, Debug, PartialEq)]
+#[burk(binary)]
 pub enum Response {
     #[burk(name = "iscal")]
     IsCalibrated(bool),
+    /// Number of moves in the queue and the max queue size.
     #[burk(name = "qs")]
-    QueueSize(u32, u32), // in queue, max queue size
+    QueueSize(u32, u32),
     #[burk(name = "pos")]
     Position(f32, f32, f32),
+    /// Whether the chess button has been pressed since [`Command::ChessButton`] was last sent.
     #[burk(name = "chessbtn")]
-    ChessButtonStatus(bool), // Checks if the chess button has been pressed since this command wast last sent.
+    ChessButtonStatus(bool),
+    /// Bottom and top arm angles measured by the angle sensors.
     #[burk(name = "magnets")]
-    Magnets(f32, f32), // Checks if the chess button has been pressed since this command wast last sent.
+    Magnets(f32, f32),
+    /// Hashes of the [`Command`] and [`Response`] schemas the firmware was built with.
+    #[burk(name = "proto")]
+    Protocol(u32, u32),
+    /// The command with this sequence id was received and has been run. The ones that
+    /// take long are acknowledged before they are run.
+    #[burk(name = "ack", since = 1)]
+    Ack(u16),
+    /// The command with this sequence id could not be parsed and should be sent again.
+    #[burk(name = "nack", since = 1)]
+    Nack(u16),
+    /// A command failed, the meaning of the second field depends on the code. Sent
+    /// before the [`Response::Ack`] of the command.
+    #[burk(name = "err", since = 2)]
+    Error(ErrorCode, u32),
+    /// Sent without being asked, see [`Command::Events`].
+    #[burk(name = "event", since = 3)]
+    Event(Event),
+    /// Answers [`Command::QueueMany`] with the number of segments that fit in the queue
+    /// and the number of moves in the queue afterwards.
+    #[burk(name = "queued", since = 4)]
+    Queued(u32, u32),
+    /// Whether the arm is moving and the number of moves in the queue.
+    #[burk(name = "status", since = 5)]
+    MotionStatus(MotionState, u32),
+    /// The value of a parameter, answers [`Command::GetParam`].
+    #[burk(name = "param", since = 6)]
+    ParamValue(Param, f32),
+    /// The number of movements that can be queued, answers [`Command::Credit`].
+    #[burk(name = "credit", since = 11)]
+    Credit(u32),
 }
 
+/// The firmware settings that can be changed without reflashing, see
+/// [`Command::SetParam`]. They are stored in flash in this order, so new ones are added
+/// last.
+#[derive(Burk, Clone, Copy, Debug, PartialEq, Eq)]
+#[burk(binary)]
+pub enum Param {
+    /// Max speed of the bottom arm stepper, in stepper degrees per second.
+    #[burk(name = "botspeed")]
+    BotArmMaxSpeed,
+    /// Max speed of the top arm stepper, in stepper degrees per second.
+    #[burk(name = "topspeed")]
+    TopArmMaxSpeed,
+    /// Max speed of the sideways stepper, in stepper degrees per second.
+    #[burk(name = "sidspeed")]
+    SidewaysMaxSpeed,
+    /// Stepper angle per top arm angle.
+    #[burk(name = "topratio")]
+    TopRatio,
+    /// Stepper angle per bottom arm angle.
+    #[burk(name = "botratio")]
+    BotRatio,
+    /// Sideways stepper degrees per meter.
+    #[burk(name = "sidratio")]
+    SidewaysDegreePerM,
+    /// Servo duty cycle when gripping.
+    #[burk(name = "gripduty")]
+    GripDuty,
+    /// Servo duty cycle when released.
+    #[burk(name = "relduty")]
+    ReleaseDuty,
+    /// Added to the bottom angle sensor reading, in degrees.
+    #[burk(name = "botoffset")]
+    BottomAngleOffset,
+    /// Added to the top angle sensor reading, in degrees.
+    #[burk(name = "topoffset")]
+    TopAngleOffset,
+    /// Max acceleration of the bottom arm stepper, in stepper degrees per second squared.
+    #[burk(name = "botaccel", since = 7)]
+    BotArmMaxAccel,
+    /// Max acceleration of the top arm stepper, in stepper degrees per second squared.
+    #[burk(name = "topaccel", since = 7)]
+    TopArmMaxAccel,
+    /// Max acceleration of the sideways stepper, in stepper degrees per second squared.
+    #[burk(name = "sidaccel", since = 7)]
+    SidewaysMaxAccel,
+    /// How the queued movements speed up and slow down, 0 for a trapezoidal velocity
+    /// profile and 1 for an S-curve.
+    #[burk(name = "profile", since = 7)]
+    MotionProfile,
+    /// An [`Event::Stall`] or [`Event::Drift`] is sent when the angle sensors and the
+    /// steppers differ by more than this many arm degrees.
+    #[burk(name = "driftlimit", since = 8)]
+    DriftLimit,
+    /// The stepper positions are corrected from the angle sensors when they differ by
+    /// more than this many arm degrees, 0 to never correct them.
+    #[burk(name = "driftfix", since = 8)]
+    DriftCorrection,
+    /// The lowest bottom arm angle a command may go to, in degrees.
+    #[burk(name = "botmin", since = 9)]
+    BotArmMin,
+    /// The highest bottom arm angle a command may go to, in degrees.
+    #[burk(name = "botmax", since = 9)]
+    BotArmMax,
+    /// The lowest top arm angle a command may go to, in degrees.
+    #[burk(name = "topmin", since = 9)]
+    TopArmMin,
+    /// The highest top arm angle a command may go to, in degrees.
+    #[burk(name = "topmax", since = 9)]
+    TopArmMax,
+    /// The lowest sideways position a command may go to, in meters from the limit switch.
+    #[burk(name = "sidmin", since = 9)]
+    SidewaysMin,
+    /// The highest sideways position a command may go to, in meters from the limit switch.
+    #[burk(name = "sidmax", since = 9)]
+    SidewaysMax,
+    /// How far the sideways calibration may go looking for the limit switch, in meters.
+    #[burk(name = "hometravel", since = 9)]
+    HomingTravel,
+    /// How long the sideways calibration may take, in seconds.
+    #[burk(name = "hometime", since = 9)]
+    HomingTimeout,
+    /// How much the firmware writes to its log port, 0 for errors only, 1 to add
+    /// warnings, 2 to add info and 3 to add debug messages.
+    #[burk(name = "loglevel", since = 13)]
+    LogLevel,
+}
+
+/// One of the steppers, in the order of [`ErrorCode::OutOfRange`].
+#[derive(Burk, Clone, Copy, Debug, PartialEq, Eq)]
+#[burk(binary)]
+pub enum Axis {
+    #[burk(name = "bot")]
+    Bottom,
+    #[burk(name = "top")]
+    Top,
+    #[burk(name = "sid")]
+    Sideways,
+}
+
+/// What the queued movements are doing, sent in [`Response::MotionStatus`].
+#[derive(Burk, Clone, Copy, Debug, PartialEq, Eq)]
+#[burk(binary)]
+pub enum MotionState {
+    #[burk(name = "idle")]
+    Idle,
+    #[burk(name = "moving")]
+    Moving,
+    /// Holding position after [`Command::Pause`], the queue is kept.
+    #[burk(name = "paused")]
+    Paused,
+}
+
+/// Something that happened on the arm, pushed as [`Response::Event`].
 #[derive(Burk, Clone, Copy, Debug, PartialEq)]
+#[burk(binary)]
+pub enum Event {
+    /// The chess button was pressed.
+    #[burk(name = "button")]
+    ChessButton,
+    /// A queued movement started, with the number of movements left in the queue.
+    #[burk(name = "segment")]
+    SegmentStarted(u32),
+    /// The last queued movement is done.
+    #[burk(name = "drained")]
+    QueueDrained,
+    /// The sideways limit switch was hit outside of calibration.
+    #[burk(name = "limit")]
+    LimitSwitch,
+    #[burk(name = "calsid")]
+    SidewaysCalibrated,
+    #[burk(name = "calarm")]
+    ArmCalibrated,
+    /// The movements were stopped by [`Command::Stop`] and the queue was flushed.
+    #[burk(name = "stopped")]
+    Stopped,
+    #[burk(name = "paused")]
+    Paused,
+    #[burk(name = "resumed")]
+    Resumed,
+    /// The angle sensors disagree with the steppers while moving, with the bottom and
+    /// top arm errors in degrees and whether the stepper positions were corrected.
+    #[burk(name = "stall", since = 8)]
+    Stall(f32, f32, bool),
+    /// Like [`Event::Stall`] but found once the arm has stopped.
+    #[burk(name = "drift", since = 8)]
+    Drift(f32, f32, bool),
+    /// The angle sensors have been calibrated, with the errors left in degrees of the
+    /// bottom and top sensor.
+    #[burk(name = "senscal", since = 10)]
+    SensorsCalibrated(f32, f32),
+    /// Room for this many more queued movements has freed up, see [`Command::Credit`].
+    #[burk(name = "credit", since = 11)]
+    Credit(u32),
+}
+
+/// Why a command failed, sent in [`Response::Error`].
+#[derive(Burk, Clone, Copy, Debug, PartialEq, Eq)]
+#[burk(binary)]
+pub enum ErrorCode {
+    /// A line or frame without a sequence id could not be parsed.
+    #[burk(name = "parse")]
+    Parse,
+    /// An angle sensor could not be read, the detail is its I2C address.
+    #[burk(name = "sensor")]
+    Sensor,
+    /// A target angle is not a finite number.
+    #[burk(name = "unreachable")]
+    Unreachable,
+    /// The movement queue is full, the detail is its size.
+    #[burk(name = "queuefull")]
+    QueueFull,
+    /// The arm has to be calibrated before it can move.
+    #[burk(name = "notcal")]
+    NotCalibrated,
+    /// A parameter can't have the value, the detail is the index of the [`Param`].
+    #[burk(name = "badvalue")]
+    BadValue,
+    /// A target is outside of the travel limits, the detail is 0 for the bottom arm, 1
+    /// for the top arm and 2 for sideways.
+    #[burk(name = "range", since = 9)]
+    OutOfRange,
+    /// The sideways calibration did not find the limit switch, the detail is 1 if it timed
+    /// out and 2 if it went too far.
+    #[burk(name = "homing", since = 9)]
+    Homing,
+    /// The sweep of [`Command::CalibrateSensors`] did not tell the field of an angle
+    /// sensor, the detail is its I2C address.
+    #[burk(name = "fit", since = 10)]
+    SensorFit,
+    /// A step size is not 1, 2, 4, 8 or 16, the detail is the step size.
+    #[burk(name = "stepsize", since = 12)]
+    StepSize,
+    /// The driver of a stepper is off, see [`Command::EnableDriver`]. The detail is the
+    /// [`Axis`].
+    #[burk(name = "disabled", since = 12)]
+    Disabled,
+    /// The arm has to stand still with an empty queue, for example to write the flash.
+    #[burk(name = "busy", since = 14)]
+    Busy,
+}
+
+/// The most segments sent in one [`Command::QueueMany`], so that it fits in a binary
+/// frame with a sequence id.
+pub const MAX_UPLOAD_SEGMENTS: usize = 15;
+
+/// A queued movement, written as `bottom,top,sideways[,speed]` in the text encoding.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Segment {
+    pub bottom: f32,
+    pub top: f32,
+    pub sideways: f32,
+    /// Speed scaling.
+    pub speed: f32,
+}
+
+impl Segment {
+    pub fn is_finite(&self) -> bool {
+        [self.bottom, self.top, self.sideways, self.speed]
+            .iter()
+            .all(|v| v.is_finite())
+    }
+}
+
+impl FromStr for Segment {
+    type Err = core::num::ParseFloatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(4, ',');
+        let mut next = || parts.next().unwrap_or("").parse::<f32>();
+        let (bottom, top, sideways) = (next()?, next()?, next()?);
+        let speed = match parts.next() {
+            Some(speed) => speed.parse()?,
+            None => 1.0,
+        };
+        Ok(Segment {
+            bottom,
+            top,
+            sideways,
+            speed,
+        })
+    }
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.bottom, self.top, self.sideways, self.speed
+        )
+    }
+}
+
+impl Field for Segment {
+    fn write(&self, w: &mut Writer) -> Result<(), FrameError> {
+        self.bottom.write(w)?;
+        self.top.write(w)?;
+        self.sideways.write(w)?;
+        self.speed.write(w)
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, FrameError> {
+        Ok(Segment {
+            bottom: f32::read(r)?,
+            top: f32::read(r)?,
+            sideways: f32::read(r)?,
+            speed: f32::read(r)?,
+        })
+    }
+}
+
+/// Commands can also be typed into a serial console, which is why the names are
+/// case-insensitive and some have longer aliases.
+///
+/// New variants are added last, with `#[burk(since = N)]` where `N` is one more than
+/// the current [`Schema::version`](burktelefon::schema::Schema::version), in both enums
+/// and in the enums of their fields like [`Param`] and [`Event`].
+#[derive(Burk, Clone, Debug, PartialEq)]
+#[burk(binary, case_insensitive)]
 pub enum Command {
-    #[burk(name = "mag")]
+    #[burk(name = "mag", alias = "magnets", reply = "Magnets")]
     Magnets,
-    #[burk(name = "pos")]
+    #[burk(name = "pos", alias = "position", reply = "Position")]
     Position,
     #[burk(name = "grip")]
     Grip,
-    #[burk(name = "rel")]
+    #[burk(name = "rel", alias = "release")]
     Release,
-    #[burk(name = "iscal")]
+    #[burk(name = "iscal", reply = "IsCalibrated")]
     IsCalibrated,
     #[burk(name = "calsid")]
     CalibrateSideways,
@@ -39,11 +360,79 @@ pub enum Command {
     #[burk(name = "mvb")]
     MoveBottomArm(f32),
     #[burk(name = "q")]
-    Queue(f32, f32, f32, f32), // sideways, top arm, bottom arm, sideways. speed scaling.
-    #[burk(name = "qs")]
+    Queue {
+        bottom: f32,
+        top: f32,
+        sideways: f32,
+        /// Speed scaling.
+        #[burk(default = 1.0)]
+        speed: f32,
+    },
+    #[burk(name = "qs", alias = "queuesize", reply = "QueueSize")]
     QueueSize,
     #[burk(name = "boot")]
     RestartToBoot,
-    #[burk(name = "chessbtn")]
-    ChessButton, // Checks if the chess button has been pressed since this command wast last sent.
+    /// Checks if the chess button has been pressed since this command was last sent.
+    #[burk(name = "chessbtn", reply = "ChessButtonStatus")]
+    ChessButton,
+    /// Asks for the protocol hashes, to detect firmware built from another version.
+    #[burk(name = "proto", reply = "Protocol")]
+    Protocol,
+    /// Enables or disables [`Response::Event`].
+    #[burk(name = "events", since = 3)]
+    Events(bool),
+    /// Queues up to [`MAX_UPLOAD_SEGMENTS`] movements at once, as many as fit in the
+    /// queue. The rest should be sent again when the queue has room.
+    #[burk(name = "qm", alias = "queuemany", since = 4, reply = "Queued")]
+    QueueMany(Vec<Segment>),
+    /// Halts all steppers where they are and flushes the queue.
+    #[burk(name = "stop", since = 5)]
+    Stop,
+    /// Halts all steppers where they are but keeps the queue, see [`Command::Resume`].
+    #[burk(name = "pause", since = 5)]
+    Pause,
+    /// Continues the movement that was paused and the queue after it.
+    #[burk(name = "resume", since = 5)]
+    Resume,
+    /// Flushes the queue, the current movement is finished.
+    #[burk(name = "clearq", alias = "clearqueue", since = 5)]
+    ClearQueue,
+    #[burk(name = "status", reply = "MotionStatus", since = 5)]
+    MotionStatus,
+    /// Changes a parameter and saves all of them to flash. Fails with [`ErrorCode::Busy`]
+    /// while the arm is moving.
+    #[burk(name = "set", alias = "setparam", since = 6)]
+    SetParam(Param, f32),
+    #[burk(name = "get", alias = "getparam", reply = "ParamValue", since = 6)]
+    GetParam(Param),
+    /// Restores the parameters the firmware was built with, also in flash. Fails like
+    /// [`Command::SetParam`] while the arm is moving.
+    #[burk(name = "resetparams", since = 6)]
+    ResetParams,
+    /// Moves each arm between the angles in degrees while measuring its angle sensor,
+    /// to correct the sensor readings from then on. The calibrations are saved to flash.
+    #[burk(name = "calsens", since = 10)]
+    CalibrateSensors {
+        bottom_from: f32,
+        bottom_to: f32,
+        top_from: f32,
+        top_to: f32,
+    },
+    /// Asks how many movements can be queued. From then on, the firmware sends
+    /// [`Event::Credit`] as the queue empties, so that a host that keeps count can queue
+    /// movements without asking again and without ever getting [`ErrorCode::QueueFull`].
+    #[burk(name = "credit", reply = "Credit", since = 11)]
+    Credit,
+    /// Changes the microstepping of a stepper to 1, 2, 4, 8 or 16 steps per full step.
+    /// Where it is and where it is going stay the same.
+    #[burk(name = "microstep", since = 12)]
+    SetStepSize(Axis, u32),
+    /// Turns the driver of a stepper on or off, off lets the motor cool down. Turning it
+    /// off stops the movements and the motor can be turned by hand then, so the axis has
+    /// to be calibrated again after it is turned back on: the bottom and top arm with
+    /// [`Command::CalibrateArm`], which reads the angle sensors, and sideways with
+    /// [`Command::CalibrateSideways`]. Until then moving it fails with
+    /// [`ErrorCode::NotCalibrated`], and with [`ErrorCode::Disabled`] while it is off.
+    #[burk(name = "enable", since = 12)]
+    EnableDriver(Axis, bool),
 }